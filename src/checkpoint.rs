@@ -0,0 +1,205 @@
+use anyhow::{Result, Error};
+use redis::{aio::Connection, AsyncCommands, Script};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::config::CHECKPOINT_KEY;
+
+/// move checkpoint forward over contiguous completed chunks.
+/// KEYS[1] checkpoint hash, KEYS[2] completed chunks zset (score: chunk start, member: "end:hash")
+const ADVANCE_SCRIPT: &'static str = r"
+local number = tonumber(redis.call('HGET', KEYS[1], 'number') or '-1')
+redis.call('ZREMRANGEBYSCORE', KEYS[2], '-inf', number)
+while true do
+    local done = redis.call('ZRANGEBYSCORE', KEYS[2], number + 1, number + 1, 'LIMIT', 0, 1)
+    if #done == 0 then
+        break
+    end
+    local to, hash = string.match(done[1], '^(%d+):(.+)$')
+    redis.call('HSET', KEYS[1], 'number', to, 'hash', hash)
+    redis.call('ZREM', KEYS[2], done[1])
+    number = tonumber(to)
+end
+return number
+";
+
+/// take chunks dispatched before ARGV[1] that are neither waiting in a queue nor delivered to a consumer,
+/// and mark them dispatched again at ARGV[2].
+/// KEYS[1] dispatched chunks zset (score: dispatch time in ms, member: "from:to"),
+/// KEYS[2] deadlines zset of the delivered queue items (member: "processing\nitem"), KEYS[3..] zsets of waiting items.
+/// ARGV[3..] pairs of chunk and its queue item
+const STALE_SCRIPT: &'static str = r"
+local delivered = {}
+for _, member in ipairs(redis.call('ZRANGE', KEYS[2], 0, -1)) do
+    local sep = string.find(member, '\n', 1, true)
+    delivered[string.sub(member, sep + 1)] = true
+end
+local stale = {}
+for i = 3, #ARGV, 2 do
+    local chunk, item = ARGV[i], ARGV[i + 1]
+    local dispatched = redis.call('ZSCORE', KEYS[1], chunk)
+    local lost = dispatched and tonumber(dispatched) <= tonumber(ARGV[1]) and not delivered[item]
+    for k = 3, #KEYS do
+        lost = lost and not redis.call('ZSCORE', KEYS[k], item)
+    end
+    if lost then
+        redis.call('ZADD', KEYS[1], ARGV[2], chunk)
+        table.insert(stale, chunk)
+    end
+end
+return stale
+";
+
+/// Checkpoint is the last fully indexed block of a chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub number: u64,
+    pub hash: String,
+}
+
+/// CheckpointStore persists sync checkpoint by redis, one per chain and runtime
+pub struct CheckpointStore {
+    pub redis_connection: Connection,
+    pub key: String,
+}
+
+impl CheckpointStore {
+    pub fn new(redis_connection: Connection, chain: &str, runtime: &str) -> CheckpointStore {
+        CheckpointStore {
+            redis_connection,
+            key: format!("{}:{}:{}", CHECKPOINT_KEY, chain, runtime),
+        }
+    }
+
+    /// last checkpoint, `None` if nothing has been indexed yet
+    pub async fn load(&mut self) -> Result<Option<Checkpoint>> {
+        let fields: HashMap<String, String> = self.redis_connection.hgetall(&self.key).await?;
+        match (fields.get("number"), fields.get("hash")) {
+            (Some(number), Some(hash)) => Ok(Some(Checkpoint {
+                number: number.parse().map_err(|_| Error::msg("checkpoint number decode error"))?,
+                hash: hash.to_string(),
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// advance checkpoint, call it only after the block is written to meilisearch
+    pub async fn save(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        self.redis_connection.hset_multiple(&self.key, &[
+            ("number", checkpoint.number.to_string()),
+            ("hash", checkpoint.hash.to_string()),
+        ]).await?;
+        Ok(())
+    }
+
+    /// mark chunk `from..=to` as indexed and advance checkpoint if the chunk is contiguous with it.
+    /// chunks are decoded in parallel, so they may complete out of order
+    pub async fn complete(&mut self, from: u64, to: u64, hash: &str) -> Result<Option<u64>> {
+        let done_key = format!("{}:done", self.key);
+        self.redis_connection.zadd(&done_key, format!("{}:{}", to, hash), from).await?;
+        self.untrack(from, to).await?;
+        self.redis_connection.zrem(format!("{}:dead", self.key), format!("{}:{}", from, to)).await?;
+        let number: i64 = Script::new(ADVANCE_SCRIPT)
+            .key(&self.key)
+            .key(&done_key)
+            .invoke_async(&mut self.redis_connection).await?;
+        Ok(if number < 0 { None } else { Some(number as u64) })
+    }
+
+    /// last block number dispatched to decode workers
+    pub async fn dispatched(&mut self) -> Result<Option<u64>> {
+        let res = self.redis_connection.get(format!("{}:dispatched", self.key)).await?;
+        Ok(res)
+    }
+
+    /// move the dispatched cursor, chunks below it are tracked by `track` until completed
+    pub async fn set_dispatched(&mut self, number: u64) -> Result<()> {
+        self.redis_connection.set(format!("{}:dispatched", self.key), number).await?;
+        Ok(())
+    }
+
+    /// remember dispatched chunks until they complete, see `stale`
+    pub async fn track(&mut self, chunks: &[(u64, u64)]) -> Result<()> {
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        let now = now_millis();
+        let items: Vec<(u64, String)> = chunks.iter().map(|(from, to)| (now, format!("{}:{}", from, to))).collect();
+        self.redis_connection.zadd_multiple(format!("{}:inflight", self.key), &items).await?;
+        Ok(())
+    }
+
+    /// record dead lettered chunk `from..=to`, the checkpoint stops below it until it is requeued and completes
+    pub async fn dead(&mut self, from: u64, to: u64) -> Result<()> {
+        self.untrack(from, to).await?;
+        self.redis_connection.zadd(format!("{}:dead", self.key), format!("{}:{}", from, to), from).await?;
+        Ok(())
+    }
+
+    /// dead lettered chunks blocking the checkpoint, lowest first
+    pub async fn dead_chunks(&mut self) -> Result<Vec<(u64, u64)>> {
+        let dead: Vec<String> = self.redis_connection.zrange(format!("{}:dead", self.key), 0, -1).await?;
+        dead.iter().map(|chunk| parse_chunk(chunk)).collect()
+    }
+
+    /// number of tracked chunks
+    pub async fn inflight(&mut self) -> Result<usize> {
+        let res = self.redis_connection.zcard(format!("{}:inflight", self.key)).await?;
+        Ok(res)
+    }
+
+    pub async fn untrack(&mut self, from: u64, to: u64) -> Result<()> {
+        self.redis_connection.zrem(format!("{}:inflight", self.key), format!("{}:{}", from, to)).await?;
+        Ok(())
+    }
+
+    /// chunks dispatched longer than `timeout` ago and not completed, e.g. lost with a redis queue,
+    /// they would leave a gap that stalls the checkpoint. chunks whose queue `item` still waits in one of
+    /// the `waiting` zsets or is delivered per the `deadlines` zset of a reliable queue are not lost.
+    /// returned chunks count as dispatched now
+    pub async fn stale(&mut self, timeout: Duration, deadlines: &str, waiting: &[&str], item: impl Fn(u64, u64) -> Vec<u8>) -> Result<Vec<(u64, u64)>> {
+        let now = now_millis();
+        let cutoff = now.saturating_sub(timeout.as_millis() as u64);
+        let candidates: Vec<String> = self.redis_connection
+            .zrangebyscore(format!("{}:inflight", self.key), "-inf", cutoff).await?;
+        if candidates.is_empty() {
+            return Ok(vec![]);
+        }
+        let script = Script::new(STALE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.key(format!("{}:inflight", self.key)).key(deadlines);
+        for key in waiting {
+            invocation.key(*key);
+        }
+        invocation.arg(cutoff).arg(now);
+        for chunk in candidates.iter() {
+            let (from, to) = parse_chunk(chunk)?;
+            invocation.arg(chunk).arg(item(from, to));
+        }
+        let stale: Vec<String> = invocation.invoke_async(&mut self.redis_connection).await?;
+        stale.iter().map(|chunk| parse_chunk(chunk)).collect()
+    }
+
+    pub async fn clear(&mut self) -> Result<()> {
+        self.redis_connection.del(&[
+            self.key.clone(),
+            format!("{}:done", self.key),
+            format!("{}:dispatched", self.key),
+            format!("{}:inflight", self.key),
+            format!("{}:dead", self.key),
+        ]).await?;
+        Ok(())
+    }
+}
+
+/// chunk bounds of a "from:to" member
+fn parse_chunk(chunk: &str) -> Result<(u64, u64)> {
+    let mut bounds = chunk.splitn(2, ':').map(|bound| bound.parse::<u64>());
+    match (bounds.next(), bounds.next()) {
+        (Some(Ok(from)), Some(Ok(to))) => Ok((from, to)),
+        _ => Err(Error::msg(format!("block chunk {} decode error", chunk))),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -13,5 +13,72 @@ pub fn build_cli() -> App<'static, 'static> {
                 .about("explorer producer command"),
             SubCommand::with_name("consumer")
                 .about("explorer consumer command"),
+            SubCommand::with_name("backfill")
+                .about("explorer backfill command, enqueue historical block range")
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .takes_value(true)
+                    .required(true)
+                    .help("first block number of the range"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .takes_value(true)
+                    .required(true)
+                    .help("last block number of the range"))
+                .arg(Arg::with_name("chunk-size")
+                    .long("chunk-size")
+                    .takes_value(true)
+                    .help("blocks decoded by a single task"))
+                .arg(Arg::with_name("priority")
+                    .long("priority")
+                    .takes_value(true)
+                    .help("queue priority, lower pops first, tip-following work uses 0")),
+            SubCommand::with_name("dead-letters")
+                .about("explorer dead-letters command, block chunks that ran out of retries")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommands(vec![
+                    SubCommand::with_name("list")
+                        .about("list dead letters, newest first")
+                        .arg(Arg::with_name("start")
+                            .long("start")
+                            .takes_value(true)
+                            .help("index of the first dead letter"))
+                        .arg(Arg::with_name("limit")
+                            .long("limit")
+                            .takes_value(true)
+                            .help("dead letters shown")),
+                    SubCommand::with_name("inspect")
+                        .about("show a dead letter with its last error")
+                        .arg(Arg::with_name("index")
+                            .required(true)
+                            .help("dead letter index from list")),
+                    SubCommand::with_name("requeue")
+                        .about("enqueue dead letters again with fresh retries")
+                        .arg(Arg::with_name("index")
+                            .required_unless("all")
+                            .help("dead letter index from list"))
+                        .arg(Arg::with_name("all")
+                            .long("all")
+                            .conflicts_with("index")
+                            .help("requeue all dead letters")),
+                ]),
+            SubCommand::with_name("storage")
+                .about("explorer storage command, show a storage item decoded from runtime metadata")
+                .arg(Arg::with_name("module")
+                    .required(true)
+                    .help("runtime module, e.g. System"))
+                .arg(Arg::with_name("item")
+                    .required(true)
+                    .help("storage item, e.g. Number"))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("hex SCALE encoded map key, repeated for double and n maps"))
+                .arg(Arg::with_name("block")
+                    .long("block")
+                    .takes_value(true)
+                    .help("block number, defaults to the best block")),
         ])
 }
\ No newline at end of file
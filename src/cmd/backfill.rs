@@ -0,0 +1,38 @@
+use anyhow::{Result, Error};
+use crate::config::AppState;
+use crate::config::{BLOCK_QUEUE_KEY, PUSH_BATCH_SIZE};
+use crate::collections::{HasAsyncPriorityQueue, RedisPriorityQueue};
+use crate::tasks::{chunks, dispatcher, decode_block, decode_tasks, BlockRange};
+
+pub struct Backfill;
+
+impl Backfill {
+    /// enqueue historical blocks `from..=to`, chunks are popped after the tip-following ones
+    pub async fn start(app_state: &AppState<'_>, from: u64, to: u64, chunk_size: u64, priority: i32) -> Result<()> {
+        if from > to {
+            return Err(Error::msg(format!("invalid backfill range #{}..=#{}", from, to)));
+        }
+        if chunk_size == 0 {
+            return Err(Error::msg("backfill chunk size must be greater than 0"));
+        }
+
+        let redis_con = app_state.redis_client.get_async_connection().await?;
+        let mut queue = RedisPriorityQueue::new(redis_con, BLOCK_QUEUE_KEY);
+        let dispatcher = dispatcher().await?;
+
+        let chunks = chunks(&(from..=to), chunk_size);
+        for batch in chunks.chunks(PUSH_BATCH_SIZE) {
+            let block_ranges: Vec<Box<BlockRange>> = batch.iter()
+                .map(|&(from, to)| Box::new(BlockRange { from, to, priority, checkpoint: false }))
+                .collect();
+            queue.push_many(&block_ranges, Some(priority)).await?;
+            for _ in 0..decode_tasks(batch.len()) {
+                dispatcher.send_task(decode_block::new()).await?;
+            }
+        }
+
+        llog::info!("backfill block range #{}..=#{} enqueued in {} chunks with priority {}",
+                    from, to, chunks.len(), priority);
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::Result;
-use crate::config::{QUEUE_NAME, CELERY_HEARTBEAT, AppState};
+use crate::config::{QUEUE_NAME, CELERY_HEARTBEAT, AppState, BLOCK_QUEUE_KEY, BLOCK_RETRY_KEY, RETRY_POLL_INTERVAL};
+use crate::collections::RedisDelayedQueue;
 use celery::broker::RedisBroker;
 use celery::beat::{CronSchedule, DeltaSchedule};
 use celery::task::TaskResult;
-use crate::tasks::{add, long_running_task, pull};
+use crate::tasks::{add, long_running_task, pull, decode_block, decode_tasks, dispatcher};
 use substrate_subxt::Runtime;
 
 pub struct Consumer;
@@ -17,6 +18,7 @@ impl Consumer {
                 add,
                 long_running_task,
                 pull,
+                decode_block,
             ],
             task_routes = [
                 "*" => QUEUE_NAME,
@@ -27,8 +29,34 @@ impl Consumer {
         ).await?;
 
         celery.display_pretty().await;
-        celery.consume_from(&[QUEUE_NAME]).await?;
+        // the retry mover runs until the worker stops consuming
+        tokio::select! {
+            consumed = celery.consume_from(&[QUEUE_NAME]) => consumed?,
+            _ = Self::run_mover(app_state) => {}
+        }
 
         Ok(())
     }
+
+    /// promote due block chunk retries to the block queue and dispatch decode_block tasks for them
+    async fn run_mover(app_state: &AppState<'_>) {
+        loop {
+            if let Err(e) = Self::move_retries(app_state).await {
+                llog::error!("block retry mover error: {}", e);
+                tokio::time::sleep(RETRY_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    async fn move_retries(app_state: &AppState<'_>) -> Result<()> {
+        let redis_con = app_state.redis_client.get_async_connection().await?;
+        let mut delayed = RedisDelayedQueue::new(redis_con, BLOCK_RETRY_KEY, BLOCK_QUEUE_KEY);
+        let dispatcher = dispatcher().await?;
+        loop {
+            let promoted = delayed.run_mover(RETRY_POLL_INTERVAL).await?;
+            for _ in 0..decode_tasks(promoted) {
+                dispatcher.send_task(decode_block::new()).await?;
+            }
+        }
+    }
 }
\ No newline at end of file
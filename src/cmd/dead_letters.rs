@@ -0,0 +1,61 @@
+use anyhow::{Result, Error};
+use crate::config::{AppState, BLOCK_QUEUE_KEY};
+use crate::collections::{DeadLetters, DeadLetter};
+use crate::tasks::{dispatcher, decode_block, decode_tasks, BlockRange};
+
+/// dead letters shown by `list` without `--limit`
+pub const DEFAULT_LIMIT: usize = 20;
+
+pub struct DeadLetterCmd;
+
+impl DeadLetterCmd {
+    pub async fn list(app_state: &AppState<'_>, start: usize, limit: usize) -> Result<()> {
+        let mut dead_letters = Self::dead_letters(app_state).await?;
+        let total = dead_letters.len().await?;
+        for (i, letter) in dead_letters.list(start, limit).await?.iter().enumerate() {
+            println!("{:>5}  #{}..=#{}  attempts: {}  error: {}",
+                     start + i, letter.element.from, letter.element.to, letter.info.attempts, letter.info.error);
+        }
+        println!("{} dead letters in {}:dead", total, BLOCK_QUEUE_KEY);
+        Ok(())
+    }
+
+    pub async fn inspect(app_state: &AppState<'_>, index: usize) -> Result<()> {
+        let mut dead_letters = Self::dead_letters(app_state).await?;
+        let letter: DeadLetter<BlockRange> = dead_letters.get(index).await?
+            .ok_or_else(|| Error::msg(format!("dead letter {} not found", index)))?;
+        println!("blocks:     #{}..=#{}", letter.element.from, letter.element.to);
+        println!("priority:   {}", letter.element.priority);
+        println!("checkpoint: {}", letter.element.checkpoint);
+        println!("attempts:   {}", letter.info.attempts);
+        println!("dead at:    {} ms", letter.info.dead_at);
+        println!("error:      {}", letter.info.error);
+        Ok(())
+    }
+
+    /// requeue the dead letter at `index`, or all of them if `None`
+    pub async fn requeue(app_state: &AppState<'_>, index: Option<usize>) -> Result<()> {
+        let mut dead_letters = Self::dead_letters(app_state).await?;
+        let count = match index {
+            Some(index) => {
+                if !dead_letters.requeue(index).await? {
+                    return Err(Error::msg(format!("dead letter {} not found", index)));
+                }
+                1
+            }
+            None => dead_letters.requeue_all().await?,
+        };
+
+        let dispatcher = dispatcher().await?;
+        for _ in 0..decode_tasks(count) {
+            dispatcher.send_task(decode_block::new()).await?;
+        }
+        llog::info!("{} dead letters requeued to {}", count, BLOCK_QUEUE_KEY);
+        Ok(())
+    }
+
+    async fn dead_letters(app_state: &AppState<'_>) -> Result<DeadLetters<BlockRange>> {
+        let redis_con = app_state.redis_client.get_async_connection().await?;
+        Ok(DeadLetters::new(redis_con, BLOCK_QUEUE_KEY))
+    }
+}
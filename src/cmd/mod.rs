@@ -1,2 +1,5 @@
 pub mod producer;
-pub mod consumer;
\ No newline at end of file
+pub mod consumer;
+pub mod backfill;
+pub mod dead_letters;
+pub mod storage;
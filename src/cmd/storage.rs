@@ -0,0 +1,31 @@
+use anyhow::{Result, Error};
+use substrate_subxt::ClientBuilder;
+use substrate_subxt::sp_core::bytes::from_hex;
+use std::env;
+use crate::config::AppState;
+use crate::decoder::dynamic::DynamicDecoder;
+use crate::runtime::{self, Runtime};
+
+pub struct StorageCmd;
+
+impl StorageCmd {
+    /// print storage item `module.name` decoded from runtime metadata,
+    /// `keys` are the hex SCALE encoded map keys
+    pub async fn get(app_state: &AppState<'_>, module: &str, name: &str, keys: &[&str], number: Option<u64>) -> Result<()> {
+        let settings = &app_state.settings;
+        let keys = keys.iter()
+            .map(|key| from_hex(key).map_err(|_| Error::msg(format!("invalid storage key {}", key))))
+            .collect::<Result<Vec<_>>>()?;
+
+        // types come from runtime metadata whatever the decoder mode is
+        let client = runtime::register_type_sizes(ClientBuilder::<Runtime>::new())
+            .set_url(env::var("CHAIN_RPC_URL").unwrap_or_else(|_| settings.chain.rpc_url.clone()))
+            .skip_type_sizes_check()
+            .build()
+            .await?;
+        let mut decoder = DynamicDecoder::new(&settings.chain.types, app_state.redis_client.clone(), &settings.chain.name);
+        let value = decoder.decode_storage(&client, number, module, name, &keys).await?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        Ok(())
+    }
+}
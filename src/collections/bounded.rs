@@ -0,0 +1,241 @@
+use anyhow::{Result, Error};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use super::HasAsyncQueue;
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    /// free slots, push waits on it
+    slots: Semaphore,
+    /// queued elements, pop waits on it
+    items: Semaphore,
+    capacity: usize,
+}
+
+/// AsyncBoundedQueue is an in-process FIFO queue with backpressure, push waits while it is full
+/// and pop waits until an element arrives. clones share the queue, one per producer or consumer
+pub struct AsyncBoundedQueue<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for AsyncBoundedQueue<T> {
+    fn clone(&self) -> Self {
+        AsyncBoundedQueue {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send> AsyncBoundedQueue<T> {
+    pub fn new(capacity: usize) -> AsyncBoundedQueue<T> {
+        AsyncBoundedQueue {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(VecDeque::with_capacity(capacity)),
+                slots: Semaphore::new(capacity),
+                items: Semaphore::new(0),
+                capacity,
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// push without waiting, fails when full
+    pub fn try_push(&self, element: T) -> Result<()> {
+        let permit = self.shared.slots.try_acquire().map_err(|_| Error::msg("AsyncBoundedQueue is full"))?;
+        permit.forget();
+        self.enqueue(element);
+        Ok(())
+    }
+
+    /// pop without waiting, `None` when empty
+    pub fn try_pop(&self) -> Option<T> {
+        let permit = self.shared.items.try_acquire().ok()?;
+        permit.forget();
+        Some(self.dequeue())
+    }
+
+    fn enqueue(&self, element: T) {
+        self.shared.queue.lock().unwrap().push_back(element);
+        self.shared.items.add_permits(1);
+    }
+
+    /// only called holding an item permit, so the queue is not empty
+    fn dequeue(&self) -> T {
+        let element = self.shared.queue.lock().unwrap().pop_front().unwrap();
+        self.shared.slots.add_permits(1);
+        element
+    }
+}
+
+#[async_trait]
+impl<T: Clone + Send + Sync> HasAsyncQueue<T> for AsyncBoundedQueue<T> {
+    async fn push(&mut self, element: &Box<T>) -> Result<()> {
+        let permit = self.shared.slots.acquire().await?;
+        permit.forget();
+        self.enqueue((**element).clone());
+        Ok(())
+    }
+
+    async fn push_many(&mut self, elements: &[Box<T>]) -> Result<()> {
+        for element in elements {
+            self.push(element).await?;
+        }
+        Ok(())
+    }
+
+    async fn pop(&mut self) -> Result<Box<T>> {
+        let permit = self.shared.items.acquire().await?;
+        permit.forget();
+        Ok(Box::new(self.dequeue()))
+    }
+
+    /// waits for the first element, then takes up to `n - 1` more without waiting
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let mut elements = vec![self.pop().await?];
+        while elements.len() < n {
+            match self.try_pop() {
+                Some(element) => elements.push(Box::new(element)),
+                None => break,
+            }
+        }
+        Ok(elements)
+    }
+
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>> {
+        match tokio::time::timeout(timeout, self.pop()).await {
+            Ok(element) => element.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn clear(&mut self) -> Result<()> {
+        while self.try_pop().is_some() {}
+        Ok(())
+    }
+
+    async fn len(&mut self) -> Result<usize> {
+        Ok(self.shared.queue.lock().unwrap().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::time::{sleep, timeout, Instant};
+
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn push_waits_while_full() {
+        let mut queue = AsyncBoundedQueue::new(1);
+        assert_eq!(queue.capacity(), 1);
+        queue.push(&Box::new(1)).await.unwrap();
+        assert!(queue.try_push(2).is_err());
+        assert!(timeout(WAIT, queue.push(&Box::new(2))).await.is_err());
+
+        let mut consumer = queue.clone();
+        let popped = tokio::spawn(async move {
+            sleep(WAIT).await;
+            consumer.pop().await.unwrap()
+        });
+        queue.push(&Box::new(2)).await.unwrap();
+        assert_eq!(*popped.await.unwrap(), 1);
+        assert_eq!(queue.len().await.unwrap(), 1);
+        assert_eq!(queue.try_pop(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn pop_waits_until_pushed() {
+        let mut queue = AsyncBoundedQueue::<u32>::new(1);
+        assert!(queue.try_pop().is_none());
+        assert!(timeout(WAIT, queue.pop()).await.is_err());
+
+        let mut producer = queue.clone();
+        tokio::spawn(async move {
+            sleep(WAIT).await;
+            producer.push(&Box::new(7)).await.unwrap();
+        });
+        assert_eq!(*queue.pop().await.unwrap(), 7);
+        assert_eq!(queue.len().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn pop_blocking_times_out() {
+        let mut queue = AsyncBoundedQueue::<u32>::new(1);
+        let started = Instant::now();
+        assert!(queue.pop_blocking(WAIT).await.unwrap().is_none());
+        assert!(started.elapsed() >= WAIT);
+
+        queue.push(&Box::new(3)).await.unwrap();
+        assert_eq!(queue.pop_blocking(WAIT).await.unwrap().map(|element| *element), Some(3));
+    }
+
+    #[tokio::test]
+    async fn pop_many_and_clear() {
+        let mut queue = AsyncBoundedQueue::new(4);
+        queue.push_many(&[Box::new(1), Box::new(2), Box::new(3)]).await.unwrap();
+        assert!(queue.pop_many(0).await.unwrap().is_empty());
+        let popped: Vec<u32> = queue.pop_many(2).await.unwrap().into_iter().map(|element| *element).collect();
+        assert_eq!(popped, vec![1, 2]);
+        queue.clear().await.unwrap();
+        assert_eq!(queue.len().await.unwrap(), 0);
+        // cleared elements free their slots
+        for i in 0..4 {
+            queue.try_push(i).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn producers_and_consumers_keep_order() {
+        const PRODUCERS: u32 = 4;
+        const CONSUMERS: usize = 3;
+        const ELEMENTS: u32 = 200;
+        let queue = AsyncBoundedQueue::new(8);
+
+        let consumers: Vec<_> = (0..CONSUMERS).map(|_| {
+            let mut queue = queue.clone();
+            tokio::spawn(async move {
+                let mut received = vec![];
+                while let Some(element) = queue.pop_blocking(Duration::from_millis(500)).await.unwrap() {
+                    received.push(*element);
+                }
+                received
+            })
+        }).collect();
+        for producer in 0..PRODUCERS {
+            let mut queue = queue.clone();
+            tokio::spawn(async move {
+                for i in 0..ELEMENTS {
+                    queue.push(&Box::new((producer, i))).await.unwrap();
+                }
+            });
+        }
+
+        let mut received: HashMap<u32, Vec<u32>> = HashMap::new();
+        for consumer in consumers {
+            let mut last: HashMap<u32, u32> = HashMap::new();
+            for (producer, i) in consumer.await.unwrap() {
+                // every consumer sees the elements of a producer in push order
+                if let Some(previous) = last.insert(producer, i) {
+                    assert!(previous < i);
+                }
+                received.entry(producer).or_default().push(i);
+            }
+        }
+        assert_eq!(received.len(), PRODUCERS as usize);
+        for (_, mut elements) in received {
+            elements.sort_unstable();
+            assert_eq!(elements, (0..ELEMENTS).collect::<Vec<_>>());
+        }
+    }
+}
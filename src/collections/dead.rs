@@ -0,0 +1,135 @@
+use anyhow::{Result, Error};
+use codec::{Encode, Decode};
+use redis::{aio::Connection, AsyncCommands, Script};
+use std::marker::PhantomData;
+
+/// stream elements are added as a new entry without attempts.
+/// KEYS: queue, dead, dead info. ARGV: item
+const REQUEUE_DEAD_SCRIPT: &'static str = r#"
+if redis.call('LREM', KEYS[2], 1, ARGV[1]) == 0 then
+    return 0
+end
+local info = cjson.decode(redis.call('HGET', KEYS[3], ARGV[1]) or '{}')
+if not redis.call('LPOS', KEYS[2], ARGV[1]) then
+    redis.call('HDEL', KEYS[3], ARGV[1])
+end
+if info.score then
+    redis.call('ZADD', KEYS[1], info.score, ARGV[1])
+elseif info.push == 'XADD' then
+    redis.call('XADD', KEYS[1], '*', 'element', ARGV[1])
+else
+    redis.call(info.push or 'LPUSH', KEYS[1], ARGV[1])
+end
+return 1
+"#;
+
+/// why and when an element was dead lettered
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeadLetterInfo {
+    #[serde(default)]
+    pub error: String,
+    #[serde(default)]
+    pub attempts: u32,
+    /// unix time in milliseconds
+    #[serde(default)]
+    pub dead_at: u64,
+    /// priority of a priority queue element
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetter<T> {
+    pub element: Box<T>,
+    pub info: DeadLetterInfo,
+}
+
+/// DeadLetters reads the `<key>:dead` list of elements that ran out of retries, newest first
+pub struct DeadLetters<T> {
+    pub redis_connection: Connection,
+    pub key: String,
+    dead: String,
+    info: String,
+    element: PhantomData<T>,
+}
+
+impl<T: Clone + Encode + Decode> DeadLetters<T> {
+    pub fn new(redis_connection: Connection, key: &str) -> DeadLetters<T> {
+        DeadLetters {
+            redis_connection,
+            key: key.to_string(),
+            dead: format!("{}:dead", key),
+            info: format!("{}:dead:info", key),
+            element: PhantomData,
+        }
+    }
+
+    pub async fn len(&mut self) -> Result<usize> {
+        let res = self.redis_connection.llen(&self.dead).await?;
+        Ok(res)
+    }
+
+    /// dead letters `start..start + count`
+    pub async fn list(&mut self, start: usize, count: usize) -> Result<Vec<DeadLetter<T>>> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let items: Vec<Vec<u8>> = self.redis_connection
+            .lrange(&self.dead, start as isize, (start + count - 1) as isize).await?;
+        let mut letters = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            letters.push(self.letter(item).await?);
+        }
+        Ok(letters)
+    }
+
+    pub async fn get(&mut self, index: usize) -> Result<Option<DeadLetter<T>>> {
+        let item: Option<Vec<u8>> = self.redis_connection.lindex(&self.dead, index as isize).await?;
+        match item {
+            Some(item) => Ok(Some(self.letter(&item).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// move the dead letter at `index` back to its queue with fresh retries, false if there is none
+    pub async fn requeue(&mut self, index: usize) -> Result<bool> {
+        let item: Option<Vec<u8>> = self.redis_connection.lindex(&self.dead, index as isize).await?;
+        match item {
+            Some(item) => self.requeue_item(&item).await,
+            None => Ok(false),
+        }
+    }
+
+    /// move all dead letters back to their queue, returns how many
+    pub async fn requeue_all(&mut self) -> Result<usize> {
+        let mut count = 0;
+        loop {
+            let item: Option<Vec<u8>> = self.redis_connection.lindex(&self.dead, -1).await?;
+            match item {
+                Some(item) if self.requeue_item(&item).await? => count += 1,
+                _ => return Ok(count),
+            }
+        }
+    }
+
+    async fn requeue_item(&mut self, item: &[u8]) -> Result<bool> {
+        let requeued: u8 = Script::new(REQUEUE_DEAD_SCRIPT)
+            .key(&self.key)
+            .key(&self.dead)
+            .key(&self.info)
+            .arg(item)
+            .invoke_async(&mut self.redis_connection)
+            .await?;
+        Ok(requeued > 0)
+    }
+
+    async fn letter(&mut self, item: &[u8]) -> Result<DeadLetter<T>> {
+        let mut encoded: &[u8] = item;
+        let element = Box::<T>::decode(&mut encoded).map_err(|_| Error::msg("dead letter decode error"))?;
+        let info: Option<String> = self.redis_connection.hget(&self.info, item).await?;
+        let info = match info {
+            Some(info) => serde_json::from_str(&info)?,
+            None => DeadLetterInfo::default(),
+        };
+        Ok(DeadLetter { element, info })
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use codec::{Encode, Decode};
+use redis::{aio::Connection, Script};
+use std::time::Duration;
+use super::CodecSerialization;
+use super::reliable::now_millis;
+
+/// moves due elements with a priority to the target zset, others to the head of the target list,
+/// where `RedisFifoQueue` pushes.
+/// KEYS: delayed, target, scores. ARGV: now, max elements moved
+const PROMOTE_SCRIPT: &'static str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, item in ipairs(due) do
+    redis.call('ZREM', KEYS[1], item)
+    local score = redis.call('HGET', KEYS[3], item)
+    if score then
+        redis.call('HDEL', KEYS[3], item)
+        redis.call('ZADD', KEYS[2], score, item)
+    else
+        redis.call('LPUSH', KEYS[2], item)
+    end
+end
+return #due
+"#;
+
+/// elements promoted by one `promote_due` call
+const PROMOTE_BATCH_SIZE: usize = 1000;
+
+/// RedisDelayedQueue holds elements in a zset scored by their due time in milliseconds,
+/// `promote_due` moves due elements to the target `RedisFifoQueue` key, or to the target
+/// `RedisPriorityQueue` key for elements pushed with a priority.
+/// pushing an element already waiting reschedules it
+pub struct RedisDelayedQueue<'a> {
+    pub redis_connection: Connection,
+    pub key: &'a str,
+    /// key of the queue due elements go to
+    pub target: &'a str,
+    /// priorities of the waiting elements
+    scores: String,
+}
+
+impl<'a> RedisDelayedQueue<'a> {
+    pub fn new(redis_connection: Connection, key: &'a str, target: &'a str) -> RedisDelayedQueue<'a> {
+        RedisDelayedQueue {
+            redis_connection,
+            key,
+            target,
+            scores: scores_key(key),
+        }
+    }
+
+    /// move due elements to the target queue, returns how many
+    pub async fn promote_due(&mut self) -> Result<usize> {
+        let mut count = 0;
+        loop {
+            let promoted: usize = Script::new(PROMOTE_SCRIPT)
+                .key(self.key)
+                .key(self.target)
+                .key(&self.scores)
+                .arg(now_millis())
+                .arg(PROMOTE_BATCH_SIZE)
+                .invoke_async(&mut self.redis_connection)
+                .await?;
+            count += promoted;
+            if promoted < PROMOTE_BATCH_SIZE {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// call `promote_due` every `interval` until elements were promoted, returns how many
+    pub async fn run_mover(&mut self, interval: Duration) -> Result<usize> {
+        loop {
+            let promoted = self.promote_due().await?;
+            if promoted > 0 {
+                llog::debug!("{} promoted {} due elements to {}", self.key, promoted, self.target);
+                return Ok(promoted);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+impl<'a, T: Clone + Encode + Decode> CodecSerialization<T> for RedisDelayedQueue<'a> {
+    fn name(&self) -> &'static str {
+        "redis-delayed-queue"
+    }
+}
+
+/// key of the priorities of the elements waiting in delayed queue `key`
+pub(crate) fn scores_key(key: &str) -> String {
+    format!("{}:scores", key)
+}
+
+/// `base * 2^attempt` capped at `max`
+pub fn backoff(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+    base.checked_mul(factor).map_or(max, |delay| delay.min(max))
+}
+
+/// block retries are pushed atomically from the processing list by `Reliable::nack_delayed`,
+/// only tests push directly
+#[cfg(test)]
+impl<'a> RedisDelayedQueue<'a> {
+    /// `priority` is the score in a target `RedisPriorityQueue`, `None` for a target `RedisFifoQueue`
+    pub async fn push_at<T: Clone + Encode + Decode>(&mut self, element: &Box<T>, priority: Option<i32>, due: std::time::SystemTime) -> Result<()> {
+        let encode_res: Vec<u8> = CodecSerialization::<T>::encode(self, element);
+        let due = due.duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| anyhow::Error::msg("RedisDelayedQueue due before unix epoch"))?;
+        let mut pipe = redis::pipe();
+        pipe.atomic().zadd(self.key, &encode_res, due.as_millis() as u64).ignore();
+        match priority {
+            Some(priority) => pipe.hset(&self.scores, &encode_res, priority).ignore(),
+            None => pipe.hdel(&self.scores, &encode_res).ignore(),
+        };
+        pipe.query_async::<_, ()>(&mut self.redis_connection).await?;
+        Ok(())
+    }
+
+    pub async fn push_after<T: Clone + Encode + Decode>(&mut self, element: &Box<T>, priority: Option<i32>, delay: Duration) -> Result<()> {
+        self.push_at(element, priority, std::time::SystemTime::now() + delay).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+    use crate::collections::tests::test_connection;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(60);
+        assert_eq!(backoff(0, base, max), base);
+        assert_eq!(backoff(1, base, max), Duration::from_secs(2));
+        assert_eq!(backoff(5, base, max), Duration::from_secs(32));
+        assert_eq!(backoff(6, base, max), max);
+        assert_eq!(backoff(0, Duration::from_secs(0), max), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let max = Duration::from_secs(u64::MAX);
+        assert_eq!(backoff(31, Duration::from_secs(1), Duration::from_secs(60)), Duration::from_secs(60));
+        assert_eq!(backoff(32, Duration::from_secs(1), Duration::from_secs(60)), Duration::from_secs(60));
+        assert_eq!(backoff(u32::MAX, Duration::from_millis(1), Duration::from_secs(60)), Duration::from_secs(60));
+        assert_eq!(backoff(31, Duration::from_secs(u64::MAX / 2), max), max);
+    }
+
+    #[test]
+    fn scores_key_is_namespaced() {
+        assert_eq!(scores_key("explorer:blocks:retry"), "explorer:blocks:retry:scores");
+    }
+
+    #[tokio::test]
+    async fn promotes_due_elements_by_priority() {
+        let (fifo_con, priority_con, mut con) = match (test_connection().await, test_connection().await, test_connection().await) {
+            (Some(fifo_con), Some(priority_con), Some(con)) => (fifo_con, priority_con, con),
+            _ => return,
+        };
+        let (fifo_key, fifo_target) = ("explorer:test:delayed:fifo", "explorer:test:delayed:fifo:target");
+        let (priority_key, priority_target) = ("explorer:test:delayed:priority", "explorer:test:delayed:priority:target");
+        let keys = vec![fifo_key.to_string(), scores_key(fifo_key), fifo_target.to_string(),
+                        priority_key.to_string(), scores_key(priority_key), priority_target.to_string()];
+        let _: () = con.del(&keys).await.unwrap();
+
+        let mut fifo = RedisDelayedQueue::new(fifo_con, fifo_key, fifo_target);
+        fifo.push_after(&Box::new(1u32), None, Duration::from_secs(0)).await.unwrap();
+        fifo.push_after(&Box::new(2u32), None, Duration::from_secs(60)).await.unwrap();
+        assert_eq!(fifo.promote_due().await.unwrap(), 1);
+        let promoted: Vec<Vec<u8>> = con.lrange(fifo_target, 0, -1).await.unwrap();
+        assert_eq!(promoted, vec![1u32.encode()]);
+
+        let mut priority = RedisDelayedQueue::new(priority_con, priority_key, priority_target);
+        priority.push_after(&Box::new(3u32), Some(7), Duration::from_secs(0)).await.unwrap();
+        // pushing again reschedules the element with its new priority
+        priority.push_after(&Box::new(4u32), Some(1), Duration::from_secs(60)).await.unwrap();
+        priority.push_after(&Box::new(4u32), Some(5), Duration::from_secs(0)).await.unwrap();
+        assert_eq!(priority.promote_due().await.unwrap(), 2);
+        let promoted: Vec<(Vec<u8>, i32)> = con.zrange_withscores(priority_target, 0, -1).await.unwrap();
+        assert_eq!(promoted, vec![(4u32.encode(), 5), (3u32.encode(), 7)]);
+        let scores: usize = con.hlen(scores_key(priority_key)).await.unwrap();
+        assert_eq!(scores, 0);
+        let _: () = con.del(&keys).await.unwrap();
+    }
+}
@@ -1,18 +1,44 @@
 use async_trait::async_trait;
 use anyhow::{Result, Error};
 use codec::{Encode, Decode, Error as CodecError};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+mod reliable;
+mod dead;
+// library queues without a caller yet, compiled and exercised by their tests
+#[cfg(test)]
+mod bounded;
+#[cfg(test)]
+mod stream;
+mod delayed;
+
+pub use reliable::{Reliable, Release};
+pub use dead::{DeadLetters, DeadLetter, DeadLetterInfo};
+pub use delayed::{RedisDelayedQueue, backoff};
 
 pub trait HasQueue<T: Clone> {
     fn push(&mut self, element: T) -> Result<()>;
     fn pop(&mut self) -> Result<T>;
+    /// next element `pop` returns, without removing it
+    fn peek(&self) -> Option<&T>;
+    /// remove all elements in pop order
+    fn drain(&mut self) -> Vec<T>;
     fn clear(&mut self) -> Result<()>;
     fn len(&mut self) -> usize;
+    fn is_full(&self) -> bool;
 }
 
 #[async_trait]
 pub trait HasAsyncQueue<T: Clone> {
     async fn push(&mut self, element: &Box<T>) -> Result<()>;
+    /// push in one round trip, popped in the same order as pushed one by one
+    async fn push_many(&mut self, elements: &[Box<T>]) -> Result<()>;
     async fn pop(&mut self) -> Result<Box<T>>;
+    /// pop up to `n` elements at once, fewer if the queue runs empty
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>>;
+    /// wait up to `timeout` for an element, `None` if the queue stayed empty
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>>;
     async fn clear(&mut self) -> Result<()>;
     async fn len(&mut self) -> Result<usize>;
 }
@@ -20,11 +46,54 @@ pub trait HasAsyncQueue<T: Clone> {
 #[async_trait]
 pub trait HasAsyncPriorityQueue<T: Clone> {
     async fn push(&mut self, element: &Box<T>,priority: Option<i32>) -> Result<()>;
+    /// push in one round trip, all with the same priority
+    async fn push_many(&mut self, elements: &[Box<T>], priority: Option<i32>) -> Result<()>;
     async fn pop(&mut self) -> Result<Box<T>>;
+    /// pop up to `n` elements at once, fewer if the queue runs empty
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>>;
+    /// wait up to `timeout` for an element, `None` if the queue stayed empty
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>>;
     async fn clear(&mut self) -> Result<()>;
     async fn len(&mut self) -> Result<usize>;
 }
 
+/// which priority pops first from a priority queue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    /// lowest priority value first
+    Min,
+    /// highest priority value first
+    Max,
+}
+
+impl Order {
+    pub fn pop_command(&self) -> &'static str {
+        match self {
+            Order::Min => "ZPOPMIN",
+            Order::Max => "ZPOPMAX",
+        }
+    }
+
+    pub fn blocking_pop_command(&self) -> &'static str {
+        match self {
+            Order::Min => "BZPOPMIN",
+            Order::Max => "BZPOPMAX",
+        }
+    }
+}
+
+/// at-least-once delivery, popped elements stay in a processing list until acked
+#[async_trait]
+pub trait HasReliableQueue<T: Clone> {
+    /// element processed, drop it from the processing list
+    async fn ack(&mut self, element: &Box<T>) -> Result<()>;
+    /// element failed with `error`, return it to the queue to be delivered again
+    /// or to the `<key>:dead` list once it ran out of retries
+    async fn nack(&mut self, element: &Box<T>, error: &str) -> Result<Release>;
+    /// return elements whose visibility timeout expired, e.g. of a crashed worker, returns how many
+    async fn requeue_expired(&mut self) -> Result<usize>;
+}
+
 /// input out serialize
 pub trait CodecSerialization<T: Clone + Encode + Decode + ?Sized> {
     fn name(&self) -> &'static str;
@@ -42,111 +111,149 @@ pub trait CodecSerialization<T: Clone + Encode + Decode + ?Sized> {
 }
 
 
-/// FifoQueue is FIFO Queue data structure by memory
+/// FifoQueue is FIFO Queue data structure by memory, a ring buffer of at most `capacity` elements
 #[derive(Debug)]
 pub struct FifoQueue<T: Clone> {
-    queue: Vec<T>,
+    queue: VecDeque<T>,
     capacity: usize,
+    /// push drops the oldest element instead of failing when full
+    overwrite: bool,
 }
 
 impl<T: Clone> FifoQueue<T> {
     pub fn new(capacity: usize) -> FifoQueue<T> {
         FifoQueue {
-            queue: vec![],
+            queue: VecDeque::with_capacity(capacity),
             capacity,
+            overwrite: false,
+        }
+    }
+
+    /// queue dropping the oldest element when pushing to a full queue
+    pub fn with_overwrite(capacity: usize) -> FifoQueue<T> {
+        FifoQueue {
+            overwrite: true,
+            ..FifoQueue::new(capacity)
         }
     }
 
     pub fn capacity(&self) -> usize {
         self.capacity
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
 }
 
 impl<T: Clone> HasQueue<T> for FifoQueue<T> {
     fn push(&mut self, element: T) -> Result<()> {
-        if self.queue.len() < self.capacity {
-            self.queue.push(element);
-            Ok(())
-        } else {
-            Err(Error::msg("FifoQueue is full"))
+        if self.is_full() {
+            if !self.overwrite || self.capacity == 0 {
+                return Err(Error::msg("FifoQueue is full"));
+            }
+            self.queue.pop_front();
         }
+        self.queue.push_back(element);
+        Ok(())
     }
 
     fn pop(&mut self) -> Result<T> {
-        match self.queue.first() {
-            Some(val) => Ok(val.clone()),
-            None => Err(Error::msg("FifoQueue is empty"))
-        }
+        self.queue.pop_front().ok_or_else(|| Error::msg("FifoQueue is empty"))
+    }
+
+    fn peek(&self) -> Option<&T> {
+        self.queue.front()
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        self.queue.drain(..).collect()
     }
 
     fn clear(&mut self) -> Result<()> {
-        if self.queue.len() > 0 {
-            Ok(self.queue.clear())
-        } else {
-            Err(Error::msg("FifoQueue is empty"))
-        }
+        self.queue.clear();
+        Ok(())
     }
 
     fn len(&mut self) -> usize {
-        if self.queue.len() > 0 {
-            self.queue.len()
-        } else {
-            0
-        }
+        self.queue.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
     }
 }
 
-/// LifoQueue is FIFO Queue data structure by memory
+/// LifoQueue is LIFO Queue data structure by memory, a ring buffer of at most `capacity` elements
 #[derive(Debug)]
 pub struct LifoQueue<T: Clone> {
-    queue: Vec<T>,
+    queue: VecDeque<T>,
     capacity: usize,
+    /// push drops the oldest element instead of failing when full
+    overwrite: bool,
 }
 
 impl<T: Clone> LifoQueue<T> {
     pub fn new(capacity: usize) -> LifoQueue<T> {
         LifoQueue {
-            queue: vec![],
+            queue: VecDeque::with_capacity(capacity),
             capacity,
+            overwrite: false,
+        }
+    }
+
+    /// queue dropping the oldest element when pushing to a full queue
+    pub fn with_overwrite(capacity: usize) -> LifoQueue<T> {
+        LifoQueue {
+            overwrite: true,
+            ..LifoQueue::new(capacity)
         }
     }
 
     pub fn capacity(&self) -> usize {
         self.capacity
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
 }
 
 impl<T: Clone> HasQueue<T> for LifoQueue<T> {
     fn push(&mut self, element: T) -> Result<()> {
-        if self.queue.len() < self.capacity {
-            self.queue.push(element);
-            Ok(())
-        } else {
-            Err(Error::msg("LifoQueue is full"))
+        if self.is_full() {
+            if !self.overwrite || self.capacity == 0 {
+                return Err(Error::msg("LifoQueue is full"));
+            }
+            self.queue.pop_front();
         }
+        self.queue.push_back(element);
+        Ok(())
     }
 
     fn pop(&mut self) -> Result<T> {
-        match self.queue.pop() {
-            Some(val) => Ok(val.clone()),
-            None => Err(Error::msg("LifoQueue is empty"))
-        }
+        self.queue.pop_back().ok_or_else(|| Error::msg("LifoQueue is empty"))
+    }
+
+    fn peek(&self) -> Option<&T> {
+        self.queue.back()
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        self.queue.drain(..).rev().collect()
     }
 
     fn clear(&mut self) -> Result<()> {
-        if self.queue.len() > 0 {
-            Ok(self.queue.clear())
-        } else {
-            Err(Error::msg("LifoQueue is empty"))
-        }
+        self.queue.clear();
+        Ok(())
     }
 
     fn len(&mut self) -> usize {
-        if self.queue.len() > 0 {
-            self.queue.len()
-        } else {
-            0
-        }
+        self.queue.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
     }
 }
 
@@ -158,6 +265,7 @@ use crate::config::REDIS_TIMEOUT;
 pub struct RedisLifoQueue<'a> {
     pub redis_connection: Connection,
     pub key: &'a str,
+    pub reliable: Option<Reliable>,
 }
 
 impl<'a> RedisLifoQueue<'a> {
@@ -165,8 +273,23 @@ impl<'a> RedisLifoQueue<'a> {
         RedisLifoQueue {
             redis_connection,
             key,
+            reliable: None,
         }
     }
+
+    /// pop into the processing list of `worker`, see `HasReliableQueue`
+    pub fn reliable(mut self, worker: &str, visibility_timeout: Duration) -> Self {
+        self.reliable = Some(Reliable::new(self.key, worker, visibility_timeout));
+        self
+    }
+
+    /// dead letter elements still failing after `max_retries` redeliveries, reliable mode only
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        if let Some(reliable) = self.reliable.as_mut() {
+            reliable.max_retries = Some(max_retries);
+        }
+        self
+    }
 }
 
 impl<'a, T: Clone + Encode + Decode> CodecSerialization<T> for RedisLifoQueue<'a> {
@@ -183,12 +306,67 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncQueue<T> for RedisLifoQueue<
         Ok(res)
     }
 
+    async fn push_many(&mut self, elements: &[Box<T>]) -> Result<()> {
+        if elements.is_empty() {
+            return Ok(());
+        }
+        let encode_res: Vec<Vec<u8>> = elements.iter().map(|element| self.encode(element)).collect();
+        self.redis_connection.lpush::<&str, Vec<Vec<u8>>, ()>(self.key, encode_res).await?;
+        Ok(())
+    }
+
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let pop_res: Vec<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_list(&mut self.redis_connection, self.key, "LEFT", n).await?,
+            None => redis::cmd("LPOP")
+                .arg(self.key)
+                .arg(n)
+                .query_async::<_, Option<Vec<Vec<u8>>>>(&mut self.redis_connection)
+                .await?
+                .unwrap_or_default(),
+        };
+        pop_res.iter().map(|pop_res| {
+            let mut encode_res: &[u8] = pop_res;
+            Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisLifoQueue pop decode error"))
+        }).collect()
+    }
+
     async fn pop(&mut self) -> Result<Box<T>> {
-        let pop_res: Vec<u8> = self.redis_connection.lpop::<&str, Vec<u8>>(self.key).await?;
+        if let Some(reliable) = self.reliable.as_ref() {
+            let reserved = reliable.reserve_list(&mut self.redis_connection, self.key, "LEFT", 1).await?
+                .pop()
+                .ok_or_else(|| Error::msg("RedisLifoQueue is empty"))?;
+            let mut encode_res: &[u8] = &reserved;
+            return Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisLifoQueue pop decode error"));
+        }
+        let pop_res: Vec<u8> = self.redis_connection.lpop::<&str, Option<Vec<u8>>>(self.key).await?
+            .ok_or_else(|| Error::msg("RedisLifoQueue is empty"))?;
         let mut encode_res: &[u8] = &pop_res;
         Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisLifoQueue pop decode error"))
     }
 
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>> {
+        let pop_res: Option<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_list_blocking(&mut self.redis_connection, self.key, "LEFT", timeout).await?,
+            None => redis::cmd("BLPOP")
+                .arg(self.key)
+                .arg(timeout.as_secs_f64())
+                .query_async::<_, Option<(String, Vec<u8>)>>(&mut self.redis_connection)
+                .await?
+                .map(|(_, element)| element),
+        };
+        match pop_res {
+            Some(pop_res) => {
+                let mut encode_res: &[u8] = &pop_res;
+                Box::<T>::decode(&mut encode_res).map(Some).map_err(|_| Error::msg("RedisLifoQueue pop decode error"))
+            }
+            None => Ok(None),
+        }
+    }
+
     async fn clear(&mut self) -> Result<()> {
         self.redis_connection.del(self.key).await?;
         Ok(())
@@ -200,11 +378,37 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncQueue<T> for RedisLifoQueue<
     }
 }
 
+#[async_trait]
+impl<'a, T: Clone + Encode + Decode + Sync + Send> HasReliableQueue<T> for RedisLifoQueue<'a> {
+    async fn ack(&mut self, element: &Box<T>) -> Result<()> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisLifoQueue is not reliable"))?;
+        if !reliable.ack(&mut self.redis_connection, &element.encode()).await? {
+            llog::warn!("RedisLifoQueue {} acked element was already requeued", self.key);
+        }
+        Ok(())
+    }
+
+    async fn nack(&mut self, element: &Box<T>, error: &str) -> Result<Release> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisLifoQueue is not reliable"))?;
+        let release = reliable.nack(&mut self.redis_connection, self.key, &element.encode(), "LPUSH", error).await?;
+        if release == Release::Dead {
+            llog::warn!("{} element moved to {} after too many retries: {}", self.key, reliable.dead, error);
+        }
+        Ok(release)
+    }
+
+    async fn requeue_expired(&mut self) -> Result<usize> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisLifoQueue is not reliable"))?;
+        reliable.requeue_expired(&mut self.redis_connection, self.key, "LPUSH").await
+    }
+}
+
 
 /// RedisFifoQueue is FIFO Queue data structure by redis
 pub struct RedisFifoQueue<'a> {
     pub redis_connection: Connection,
     pub key: &'a str,
+    pub reliable: Option<Reliable>,
 }
 
 impl<'a> RedisFifoQueue<'a> {
@@ -212,8 +416,23 @@ impl<'a> RedisFifoQueue<'a> {
         RedisFifoQueue {
             redis_connection,
             key,
+            reliable: None,
         }
     }
+
+    /// pop into the processing list of `worker`, see `HasReliableQueue`
+    pub fn reliable(mut self, worker: &str, visibility_timeout: Duration) -> Self {
+        self.reliable = Some(Reliable::new(self.key, worker, visibility_timeout));
+        self
+    }
+
+    /// dead letter elements still failing after `max_retries` redeliveries, reliable mode only
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        if let Some(reliable) = self.reliable.as_mut() {
+            reliable.max_retries = Some(max_retries);
+        }
+        self
+    }
 }
 
 impl<'a, T: Clone + Encode + Decode> CodecSerialization<T> for RedisFifoQueue<'a> {
@@ -230,12 +449,67 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncQueue<T> for RedisFifoQueue<
         Ok(res)
     }
 
+    async fn push_many(&mut self, elements: &[Box<T>]) -> Result<()> {
+        if elements.is_empty() {
+            return Ok(());
+        }
+        let encode_res: Vec<Vec<u8>> = elements.iter().map(|element| self.encode(element)).collect();
+        self.redis_connection.lpush::<&str, Vec<Vec<u8>>, ()>(self.key, encode_res).await?;
+        Ok(())
+    }
+
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let pop_res: Vec<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_list(&mut self.redis_connection, self.key, "RIGHT", n).await?,
+            None => redis::cmd("RPOP")
+                .arg(self.key)
+                .arg(n)
+                .query_async::<_, Option<Vec<Vec<u8>>>>(&mut self.redis_connection)
+                .await?
+                .unwrap_or_default(),
+        };
+        pop_res.iter().map(|pop_res| {
+            let mut encode_res: &[u8] = pop_res;
+            Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisFifoQueue pop decode error"))
+        }).collect()
+    }
+
     async fn pop(&mut self) -> Result<Box<T>> {
-        let pop_res: Vec<u8> = self.redis_connection.rpop::<&str, Vec<u8>>(self.key).await?;
+        if let Some(reliable) = self.reliable.as_ref() {
+            let reserved = reliable.reserve_list(&mut self.redis_connection, self.key, "RIGHT", 1).await?
+                .pop()
+                .ok_or_else(|| Error::msg("RedisFifoQueue is empty"))?;
+            let mut encode_res: &[u8] = &reserved;
+            return Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisFifoQueue pop decode error"));
+        }
+        let pop_res: Vec<u8> = self.redis_connection.rpop::<&str, Option<Vec<u8>>>(self.key).await?
+            .ok_or_else(|| Error::msg("RedisFifoQueue is empty"))?;
         let mut encode_res: &[u8] = &pop_res;
         Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisFifoQueue pop decode error"))
     }
 
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>> {
+        let pop_res: Option<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_list_blocking(&mut self.redis_connection, self.key, "RIGHT", timeout).await?,
+            None => redis::cmd("BRPOP")
+                .arg(self.key)
+                .arg(timeout.as_secs_f64())
+                .query_async::<_, Option<(String, Vec<u8>)>>(&mut self.redis_connection)
+                .await?
+                .map(|(_, element)| element),
+        };
+        match pop_res {
+            Some(pop_res) => {
+                let mut encode_res: &[u8] = &pop_res;
+                Box::<T>::decode(&mut encode_res).map(Some).map_err(|_| Error::msg("RedisFifoQueue pop decode error"))
+            }
+            None => Ok(None),
+        }
+    }
+
     async fn clear(&mut self) -> Result<()> {
         self.redis_connection.del(self.key).await?;
         Ok(())
@@ -247,10 +521,37 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncQueue<T> for RedisFifoQueue<
     }
 }
 
+#[async_trait]
+impl<'a, T: Clone + Encode + Decode + Sync + Send> HasReliableQueue<T> for RedisFifoQueue<'a> {
+    async fn ack(&mut self, element: &Box<T>) -> Result<()> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisFifoQueue is not reliable"))?;
+        if !reliable.ack(&mut self.redis_connection, &element.encode()).await? {
+            llog::warn!("RedisFifoQueue {} acked element was already requeued", self.key);
+        }
+        Ok(())
+    }
+
+    async fn nack(&mut self, element: &Box<T>, error: &str) -> Result<Release> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisFifoQueue is not reliable"))?;
+        let release = reliable.nack(&mut self.redis_connection, self.key, &element.encode(), "RPUSH", error).await?;
+        if release == Release::Dead {
+            llog::warn!("{} element moved to {} after too many retries: {}", self.key, reliable.dead, error);
+        }
+        Ok(release)
+    }
+
+    async fn requeue_expired(&mut self) -> Result<usize> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisFifoQueue is not reliable"))?;
+        reliable.requeue_expired(&mut self.redis_connection, self.key, "RPUSH").await
+    }
+}
+
 /// RedisPriorityQueue is Priority Queue data structure by redis
 pub struct RedisPriorityQueue<'a> {
     pub redis_connection: Connection,
     pub key: &'a str,
+    pub reliable: Option<Reliable>,
+    pub order: Order,
 }
 
 impl<'a> RedisPriorityQueue<'a> {
@@ -258,7 +559,46 @@ impl<'a> RedisPriorityQueue<'a> {
         RedisPriorityQueue {
             redis_connection,
             key,
+            reliable: None,
+            order: Order::Min,
+        }
+    }
+
+    /// `Order::Min` by default
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// pop into the processing list of `worker`, see `HasReliableQueue`
+    pub fn reliable(mut self, worker: &str, visibility_timeout: Duration) -> Self {
+        self.reliable = Some(Reliable::new(self.key, worker, visibility_timeout));
+        self
+    }
+
+    /// dead letter elements still failing after `max_retries` redeliveries, reliable mode only
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        if let Some(reliable) = self.reliable.as_mut() {
+            reliable.max_retries = Some(max_retries);
         }
+        self
+    }
+
+    /// deliveries of a popped element so far, reliable mode only
+    pub async fn attempts<T: Encode>(&mut self, element: &Box<T>) -> Result<u32> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisPriorityQueue is not reliable"))?;
+        reliable.attempts(&mut self.redis_connection, &element.encode()).await
+    }
+
+    /// `nack` an element to wait `delay` in the `RedisDelayedQueue` at `delayed` before it is
+    /// promoted back to this queue with its priority, reliable mode only
+    pub async fn nack_delayed<T: Encode>(&mut self, element: &Box<T>, delayed: &str, delay: Duration, error: &str) -> Result<Release> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisPriorityQueue is not reliable"))?;
+        let release = reliable.nack_delayed(&mut self.redis_connection, self.key, &element.encode(), delayed, delay, error).await?;
+        if release == Release::Dead {
+            llog::warn!("{} element moved to {} after too many retries: {}", self.key, reliable.dead, error);
+        }
+        Ok(release)
     }
 }
 
@@ -279,21 +619,58 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncPriorityQueue<T> for RedisPr
         let res = self.redis_connection.zadd(self.key, score, encode_res).await?;
         Ok(res)
     }
+
+    async fn push_many(&mut self, elements: &[Box<T>], priority: Option<i32>) -> Result<()> {
+        if elements.is_empty() {
+            return Ok(());
+        }
+        let score = priority.unwrap_or(1);
+        let items: Vec<(i32, Vec<u8>)> = elements.iter().map(|element| (score, self.encode(element))).collect();
+        self.redis_connection.zadd_multiple::<&str, i32, Vec<u8>, ()>(self.key, &items).await?;
+        Ok(())
+    }
     async fn pop(&mut self) -> Result<Box<T>> {
-        let mut pipe = redis::pipe();
-        let results: Vec<u8>= pipe.cmd("ZRANGE")
-            .arg(self.key)
-            .arg(0)
-            .arg(0)
-            .ignore()
-            .cmd("ZREMRANGEBYRANK")
-            .arg(self.key)
-            .arg(0)
-            .arg(0)
-            .ignore()
-            .query_async(&mut self.redis_connection).await?;
-        let mut encode_res: &[u8] = &results;
-        Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisPriorityQueue pop decode error"))
+        self.pop_many(1).await?.pop().ok_or_else(|| Error::msg("RedisPriorityQueue is empty"))
+    }
+
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let pop_res: Vec<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_zset(&mut self.redis_connection, self.key, self.order, n).await?,
+            None => redis::cmd(self.order.pop_command())
+                .arg(self.key)
+                .arg(n)
+                .query_async::<_, Vec<(Vec<u8>, String)>>(&mut self.redis_connection)
+                .await?
+                .into_iter()
+                .map(|(element, _)| element)
+                .collect(),
+        };
+        pop_res.iter().map(|pop_res| {
+            let mut encode_res: &[u8] = pop_res;
+            Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisPriorityQueue pop decode error"))
+        }).collect()
+    }
+
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>> {
+        let pop_res: Option<Vec<u8>> = match self.reliable.as_ref() {
+            Some(reliable) => reliable.reserve_zset_blocking(&mut self.redis_connection, self.key, self.order, timeout).await?,
+            None => redis::cmd(self.order.blocking_pop_command())
+                .arg(self.key)
+                .arg(timeout.as_secs_f64())
+                .query_async::<_, Option<(String, Vec<u8>, String)>>(&mut self.redis_connection)
+                .await?
+                .map(|(_, element, _)| element),
+        };
+        match pop_res {
+            Some(pop_res) => {
+                let mut encode_res: &[u8] = &pop_res;
+                Box::<T>::decode(&mut encode_res).map(Some).map_err(|_| Error::msg("RedisPriorityQueue pop decode error"))
+            }
+            None => Ok(None),
+        }
     }
 
     async fn clear(&mut self) -> Result<()> {
@@ -307,3 +684,128 @@ impl<'a, T: Clone + Encode + Decode + Sync> HasAsyncPriorityQueue<T> for RedisPr
     }
 }
 
+#[async_trait]
+impl<'a, T: Clone + Encode + Decode + Sync + Send> HasReliableQueue<T> for RedisPriorityQueue<'a> {
+    async fn ack(&mut self, element: &Box<T>) -> Result<()> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisPriorityQueue is not reliable"))?;
+        if !reliable.ack(&mut self.redis_connection, &element.encode()).await? {
+            llog::warn!("RedisPriorityQueue {} acked element was already requeued", self.key);
+        }
+        Ok(())
+    }
+
+    async fn nack(&mut self, element: &Box<T>, error: &str) -> Result<Release> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisPriorityQueue is not reliable"))?;
+        let release = reliable.nack(&mut self.redis_connection, self.key, &element.encode(), "", error).await?;
+        if release == Release::Dead {
+            llog::warn!("{} element moved to {} after too many retries: {}", self.key, reliable.dead, error);
+        }
+        Ok(release)
+    }
+
+    async fn requeue_expired(&mut self) -> Result<usize> {
+        let reliable = self.reliable.as_ref().ok_or_else(|| Error::msg("RedisPriorityQueue is not reliable"))?;
+        reliable.requeue_expired(&mut self.redis_connection, self.key, "").await
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// connection to `REDIS_TEST_URL`, redis backed queue tests are skipped if it is not set
+    pub(crate) async fn test_connection() -> Option<Connection> {
+        let url = std::env::var("REDIS_TEST_URL").ok()?;
+        Some(RedisClient::open(url).unwrap().get_async_connection().await.unwrap())
+    }
+
+    #[test]
+    fn fifo_order() {
+        let mut queue = FifoQueue::new(3);
+        assert!(queue.pop().is_err());
+        assert_eq!(queue.peek(), None);
+        for i in 1..=3 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(queue.peek(), Some(&1));
+        assert_eq!(queue.pop().unwrap(), 1);
+        queue.push(4).unwrap();
+        assert_eq!(queue.drain(), vec![2, 3, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn lifo_order() {
+        let mut queue = LifoQueue::new(3);
+        assert!(queue.pop().is_err());
+        assert_eq!(queue.peek(), None);
+        for i in 1..=3 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(queue.peek(), Some(&3));
+        assert_eq!(queue.pop().unwrap(), 3);
+        queue.push(4).unwrap();
+        assert_eq!(queue.drain(), vec![4, 2, 1]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn full_boundary() {
+        let mut fifo = FifoQueue::new(2);
+        let mut lifo = LifoQueue::new(2);
+        for i in 1..=2 {
+            assert!(!fifo.is_full() && !lifo.is_full());
+            fifo.push(i).unwrap();
+            lifo.push(i).unwrap();
+        }
+        assert!(fifo.is_full() && lifo.is_full());
+        assert!(fifo.push(3).is_err());
+        assert!(lifo.push(3).is_err());
+        assert_eq!(fifo.len(), 2);
+        assert_eq!(lifo.len(), 2);
+        fifo.pop().unwrap();
+        lifo.pop().unwrap();
+        assert!(!fifo.is_full() && !lifo.is_full());
+    }
+
+    #[test]
+    fn overwrite_oldest() {
+        let mut fifo = FifoQueue::with_overwrite(2);
+        let mut lifo = LifoQueue::with_overwrite(2);
+        for i in 1..=3 {
+            fifo.push(i).unwrap();
+            lifo.push(i).unwrap();
+        }
+        assert_eq!(fifo.len(), 2);
+        assert_eq!(fifo.drain(), vec![2, 3]);
+        assert_eq!(lifo.len(), 2);
+        assert_eq!(lifo.drain(), vec![3, 2]);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut fifo = FifoQueue::with_overwrite(0);
+        let mut lifo = LifoQueue::with_overwrite(0);
+        assert!(fifo.is_full() && lifo.is_full());
+        assert!(fifo.push(1).is_err());
+        assert!(lifo.push(1).is_err());
+        assert!(fifo.is_empty() && lifo.is_empty());
+        assert!(FifoQueue::new(0).push(1).is_err());
+        assert!(LifoQueue::new(0).push(1).is_err());
+    }
+
+    #[test]
+    fn clear_empty() {
+        let mut fifo: FifoQueue<u8> = FifoQueue::new(2);
+        let mut lifo: LifoQueue<u8> = LifoQueue::new(2);
+        fifo.clear().unwrap();
+        lifo.clear().unwrap();
+        assert_eq!(fifo.len(), 0);
+        assert_eq!(lifo.len(), 0);
+        fifo.push(1).unwrap();
+        fifo.clear().unwrap();
+        assert!(fifo.is_empty());
+        assert_eq!(fifo.drain(), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::Result;
+use redis::{aio::Connection, AsyncCommands, Script};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Instant};
+use super::Order;
+use super::delayed::scores_key;
+
+/// first and longest sleep between polls of the blocking zset reserve
+const POLL_MIN_INTERVAL: Duration = Duration::from_millis(50);
+const POLL_MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+/// moves `item` from the `processing` list or zset back to the queue, zset elements keep their score.
+/// with a `delayed` zset the item waits there until `due` instead, the score of zset elements is kept in `scores`.
+/// elements delivered more than max retries times go to the dead letter list with `reason` instead.
+/// KEYS: queue, deadlines, attempts, dead, dead info. ARGV: LPUSH or RPUSH for lists, max retries or -1, now.
+/// processing keys are read from the deadlines zset, so this does not work on redis cluster
+const RELEASE_FUNCTION: &'static str = r#"
+local function release(processing, item, reason, delayed, scores, due)
+    local kind = redis.call('TYPE', processing).ok
+    local score
+    if kind == 'list' then
+        if redis.call('LREM', processing, 1, item) == 0 then
+            return 0
+        end
+    elseif kind == 'zset' then
+        score = redis.call('ZSCORE', processing, item)
+        if not score then
+            return 0
+        end
+        redis.call('ZREM', processing, item)
+    else
+        return 0
+    end
+    local attempts = tonumber(redis.call('HGET', KEYS[3], item) or '0')
+    local max_retries = tonumber(ARGV[2])
+    if max_retries >= 0 and attempts > max_retries then
+        redis.call('HDEL', KEYS[3], item)
+        redis.call('LPUSH', KEYS[4], item)
+        redis.call('HSET', KEYS[5], item, cjson.encode({
+            error = reason, attempts = attempts, dead_at = tonumber(ARGV[3]),
+            score = score and tonumber(score), push = ARGV[1],
+        }))
+        return 2
+    end
+    if delayed then
+        redis.call('ZADD', delayed, due, item)
+        if score then
+            redis.call('HSET', scores, item, score)
+        end
+    elseif score then
+        redis.call('ZADD', KEYS[1], score, item)
+    else
+        redis.call(ARGV[1], KEYS[1], item)
+    end
+    return 1
+end
+"#;
+
+/// KEYS: queue, processing, deadlines, attempts. ARGV: LEFT or RIGHT end to pop, deadline, count
+const RESERVE_LIST_SCRIPT: &'static str = r#"
+local items = {}
+for i = 1, tonumber(ARGV[3]) do
+    local item = redis.call('LMOVE', KEYS[1], KEYS[2], ARGV[1], 'LEFT')
+    if not item then
+        break
+    end
+    redis.call('ZADD', KEYS[3], ARGV[2], KEYS[2] .. '\n' .. item)
+    redis.call('HINCRBY', KEYS[4], item, 1)
+    table.insert(items, item)
+end
+return items
+"#;
+
+/// tracks an element BLMOVE moved into the processing list.
+/// KEYS: processing, deadlines, attempts. ARGV: item, deadline
+const TRACK_SCRIPT: &'static str = r#"
+redis.call('ZADD', KEYS[2], ARGV[2], KEYS[1] .. '\n' .. ARGV[1])
+redis.call('HINCRBY', KEYS[3], ARGV[1], 1)
+"#;
+
+/// KEYS: queue, processing, deadlines, attempts. ARGV: deadline, ZPOPMIN or ZPOPMAX, count
+const RESERVE_ZSET_SCRIPT: &'static str = r#"
+local popped = redis.call(ARGV[2], KEYS[1], ARGV[3])
+local items = {}
+for i = 1, #popped, 2 do
+    redis.call('ZADD', KEYS[2], popped[i + 1], popped[i])
+    redis.call('ZADD', KEYS[3], ARGV[1], KEYS[2] .. '\n' .. popped[i])
+    redis.call('HINCRBY', KEYS[4], popped[i], 1)
+    table.insert(items, popped[i])
+end
+return items
+"#;
+
+/// KEYS: processing, deadlines, attempts. ARGV: item
+const ACK_SCRIPT: &'static str = r#"
+redis.call('ZREM', KEYS[2], KEYS[1] .. '\n' .. ARGV[1])
+local kind = redis.call('TYPE', KEYS[1]).ok
+local removed = 0
+if kind == 'list' then
+    removed = redis.call('LREM', KEYS[1], 1, ARGV[1])
+elseif kind == 'zset' then
+    removed = redis.call('ZREM', KEYS[1], ARGV[1])
+end
+if removed > 0 then
+    redis.call('HDEL', KEYS[3], ARGV[1])
+end
+return removed
+"#;
+
+/// KEYS: release keys, processing. ARGV: release args, item, error
+const NACK_SCRIPT: &'static str = r#"
+redis.call('ZREM', KEYS[2], KEYS[6] .. '\n' .. ARGV[4])
+return release(KEYS[6], ARGV[4], ARGV[5])
+"#;
+
+/// KEYS: release keys, processing, delayed, delayed scores. ARGV: release args, item, error, due
+const NACK_DELAYED_SCRIPT: &'static str = r#"
+redis.call('ZREM', KEYS[2], KEYS[6] .. '\n' .. ARGV[4])
+return release(KEYS[6], ARGV[4], ARGV[5], KEYS[7], KEYS[8], ARGV[6])
+"#;
+
+/// elements a worker moved with BLMOVE but never tracked, e.g. it crashed in between, get a deadline,
+/// so they are requeued once it expires.
+/// KEYS: release keys, workers. ARGV: release args, deadline of untracked elements
+const REQUEUE_SCRIPT: &'static str = r#"
+local expired = redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', ARGV[3])
+local count = 0
+for _, member in ipairs(expired) do
+    local sep = string.find(member, '\n', 1, true)
+    if release(string.sub(member, 1, sep - 1), string.sub(member, sep + 1), 'visibility timeout expired') == 1 then
+        count = count + 1
+    end
+    redis.call('ZREM', KEYS[2], member)
+end
+for _, processing in ipairs(redis.call('SMEMBERS', KEYS[6])) do
+    for _, item in ipairs(redis.call('LRANGE', processing, 0, -1)) do
+        redis.call('ZADD', KEYS[2], 'NX', ARGV[4], processing .. '\n' .. item)
+    end
+end
+return count
+"#;
+
+/// Reliable keeps popped elements in a per-worker processing list until they are acked,
+/// elements not acked within the visibility timeout are requeued by `requeue_expired`
+#[derive(Debug, Clone)]
+pub struct Reliable {
+    /// elements delivered to this worker
+    pub processing: String,
+    /// deadline of every delivered element of the queue, shared by all workers
+    pub deadlines: String,
+    /// delivery attempts of every element of the queue
+    pub attempts: String,
+    /// dead letter list, see `DeadLetters`
+    pub dead: String,
+    /// processing lists of the workers reserving with BLMOVE
+    pub workers: String,
+    pub visibility_timeout: Duration,
+    /// elements failing more often are moved to the dead letter list, retried forever if `None`
+    pub max_retries: Option<u32>,
+}
+
+impl Reliable {
+    pub fn new(key: &str, worker: &str, visibility_timeout: Duration) -> Reliable {
+        Reliable {
+            processing: format!("{}:processing:{}", key, worker),
+            deadlines: format!("{}:deadlines", key),
+            attempts: format!("{}:attempts", key),
+            dead: format!("{}:dead", key),
+            workers: format!("{}:workers", key),
+            visibility_timeout,
+            max_retries: None,
+        }
+    }
+
+    /// pop up to `count` elements from the `from` end (LEFT or RIGHT) of list `key` into the processing list
+    pub async fn reserve_list(&self, con: &mut Connection, key: &str, from: &str, count: usize) -> Result<Vec<Vec<u8>>> {
+        let items = Script::new(RESERVE_LIST_SCRIPT)
+            .key(key)
+            .key(&self.processing)
+            .key(&self.deadlines)
+            .key(&self.attempts)
+            .arg(from)
+            .arg(self.deadline())
+            .arg(count)
+            .invoke_async(con)
+            .await?;
+        Ok(items)
+    }
+
+    /// pop up to `count` elements of zset `key` in `order` into the processing zset
+    pub async fn reserve_zset(&self, con: &mut Connection, key: &str, order: Order, count: usize) -> Result<Vec<Vec<u8>>> {
+        let items = Script::new(RESERVE_ZSET_SCRIPT)
+            .key(key)
+            .key(&self.processing)
+            .key(&self.deadlines)
+            .key(&self.attempts)
+            .arg(self.deadline())
+            .arg(order.pop_command())
+            .arg(count)
+            .invoke_async(con)
+            .await?;
+        Ok(items)
+    }
+
+    /// blocking `reserve_list`, `None` once `timeout` passed. BLMOVE cannot track the element in the
+    /// same step, so it is tracked right after, an element of a worker crashing in between is requeued by
+    /// `requeue_expired` one visibility timeout later
+    pub async fn reserve_list_blocking(&self, con: &mut Connection, key: &str, from: &str, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        if let Some(item) = self.reserve_list(con, key, from, 1).await?.pop() {
+            return Ok(Some(item));
+        }
+        if timeout.as_secs_f64() <= 0.0 {
+            return Ok(None);
+        }
+        let _: () = con.sadd(&self.workers, &self.processing).await?;
+        let item: Option<Vec<u8>> = redis::cmd("BLMOVE")
+            .arg(key)
+            .arg(&self.processing)
+            .arg(from)
+            .arg("LEFT")
+            .arg(timeout.as_secs_f64())
+            .query_async(con)
+            .await?;
+        if let Some(item) = &item {
+            Script::new(TRACK_SCRIPT)
+                .key(&self.processing)
+                .key(&self.deadlines)
+                .key(&self.attempts)
+                .arg(item.as_slice())
+                .arg(self.deadline())
+                .invoke_async::<_, ()>(con)
+                .await?;
+        }
+        Ok(item)
+    }
+
+    /// blocking `reserve_zset`, `None` once `timeout` passed. BZPOPMIN/BZPOPMAX cannot move the element
+    /// into the processing zset, and a popped element would be lost if the worker crashed before tracking it,
+    /// so the atomic reserve script is polled instead: an element pushed to an empty queue waits up to
+    /// `POLL_MAX_INTERVAL` and an idle worker costs a script call per poll
+    pub async fn reserve_zset_blocking(&self, con: &mut Connection, key: &str, order: Order, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let mut poll = Poll::new(timeout);
+        loop {
+            if let Some(item) = self.reserve_zset(con, key, order, 1).await?.pop() {
+                return Ok(Some(item));
+            }
+            if !poll.wait().await {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// drop a processed element, returns false if it was already requeued
+    pub async fn ack(&self, con: &mut Connection, item: &[u8]) -> Result<bool> {
+        let removed: usize = Script::new(ACK_SCRIPT)
+            .key(&self.processing)
+            .key(&self.deadlines)
+            .key(&self.attempts)
+            .arg(item)
+            .invoke_async(con)
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// return a failed element to queue `key` with `push` (LPUSH or RPUSH, ignored for zsets),
+    /// or to the dead letter list once it ran out of retries
+    pub async fn nack(&self, con: &mut Connection, key: &str, item: &[u8], push: &str, error: &str) -> Result<Release> {
+        let script = Script::new(&format!("{}{}", RELEASE_FUNCTION, NACK_SCRIPT));
+        let mut invocation = script.prepare_invoke();
+        self.release_keys(&mut invocation, key, push);
+        let released: u8 = invocation
+            .key(&self.processing)
+            .arg(item)
+            .arg(error)
+            .invoke_async(con)
+            .await?;
+        Ok(Release::from(released))
+    }
+
+    /// like `nack`, but the element waits `delay` in the `RedisDelayedQueue` at `delayed`,
+    /// which promotes it back to queue `key`
+    pub async fn nack_delayed(&self, con: &mut Connection, key: &str, item: &[u8], delayed: &str, delay: Duration, error: &str) -> Result<Release> {
+        let script = Script::new(&format!("{}{}", RELEASE_FUNCTION, NACK_DELAYED_SCRIPT));
+        let mut invocation = script.prepare_invoke();
+        self.release_keys(&mut invocation, key, "LPUSH");
+        let released: u8 = invocation
+            .key(&self.processing)
+            .key(delayed)
+            .key(scores_key(delayed))
+            .arg(item)
+            .arg(error)
+            .arg(now_millis() + delay.as_millis() as u64)
+            .invoke_async(con)
+            .await?;
+        Ok(Release::from(released))
+    }
+
+    /// deliveries of `item` so far, the current one included
+    pub async fn attempts(&self, con: &mut Connection, item: &[u8]) -> Result<u32> {
+        let attempts: Option<u32> = con.hget(&self.attempts, item).await?;
+        Ok(attempts.unwrap_or(0))
+    }
+
+    /// return elements of any worker whose visibility timeout expired to queue `key`, returns how many
+    pub async fn requeue_expired(&self, con: &mut Connection, key: &str, push: &str) -> Result<usize> {
+        let script = Script::new(&format!("{}{}", RELEASE_FUNCTION, REQUEUE_SCRIPT));
+        let mut invocation = script.prepare_invoke();
+        self.release_keys(&mut invocation, key, push);
+        let count = invocation
+            .key(&self.workers)
+            .arg(self.deadline())
+            .invoke_async(con)
+            .await?;
+        Ok(count)
+    }
+
+    fn release_keys(&self, invocation: &mut redis::ScriptInvocation, key: &str, push: &str) {
+        invocation
+            .key(key)
+            .key(&self.deadlines)
+            .key(&self.attempts)
+            .key(&self.dead)
+            .key(format!("{}:info", self.dead))
+            .arg(push)
+            .arg(self.max_retries.map(|max| max as i64).unwrap_or(-1))
+            .arg(now_millis());
+    }
+
+    fn deadline(&self) -> u64 {
+        now_millis() + self.visibility_timeout.as_millis() as u64
+    }
+}
+
+/// Poll spaces out the polls of a blocking reserve, doubling the interval up to `POLL_MAX_INTERVAL`
+struct Poll {
+    deadline: Instant,
+    interval: Duration,
+}
+
+impl Poll {
+    fn new(timeout: Duration) -> Poll {
+        Poll {
+            deadline: Instant::now() + timeout,
+            interval: POLL_MIN_INTERVAL,
+        }
+    }
+
+    /// sleep until the next poll, false once the timeout passed
+    async fn wait(&mut self) -> bool {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return false;
+        }
+        sleep(self.interval.min(self.deadline - now)).await;
+        self.interval = (self.interval * 2).min(POLL_MAX_INTERVAL);
+        true
+    }
+}
+
+/// where a released element went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Release {
+    /// not in the processing list anymore, e.g. requeued by the reaper
+    Missing,
+    Requeued,
+    Dead,
+}
+
+impl From<u8> for Release {
+    fn from(status: u8) -> Self {
+        match status {
+            1 => Release::Requeued,
+            2 => Release::Dead,
+            _ => Release::Missing,
+        }
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::tests::test_connection;
+
+    async fn clear(con: &mut Connection, key: &str, reliable: &Reliable) {
+        let _: () = con.del(vec![key, reliable.processing.as_str(), reliable.deadlines.as_str(),
+                                 reliable.attempts.as_str(), reliable.workers.as_str()]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocking_reserve_tracks_moved_elements() {
+        let mut con = match test_connection().await {
+            Some(con) => con,
+            None => return,
+        };
+        let key = "explorer:test:reliable:blocking";
+        let reliable = Reliable::new(key, "worker", Duration::from_secs(60));
+        clear(&mut con, key, &reliable).await;
+
+        assert_eq!(reliable.reserve_list_blocking(&mut con, key, "LEFT", Duration::from_millis(100)).await.unwrap(), None);
+        let _: () = con.rpush(key, "a").await.unwrap();
+        let item = reliable.reserve_list_blocking(&mut con, key, "LEFT", Duration::from_millis(100)).await.unwrap();
+        assert_eq!(item, Some(b"a".to_vec()));
+        assert_eq!(reliable.attempts(&mut con, b"a").await.unwrap(), 1);
+        assert!(reliable.ack(&mut con, b"a").await.unwrap());
+        clear(&mut con, key, &reliable).await;
+    }
+
+    #[tokio::test]
+    async fn requeue_adopts_untracked_elements() {
+        let mut con = match test_connection().await {
+            Some(con) => con,
+            None => return,
+        };
+        let key = "explorer:test:reliable:adopt";
+        let reliable = Reliable::new(key, "worker", Duration::from_secs(0));
+        clear(&mut con, key, &reliable).await;
+
+        // a worker that crashed after BLMOVE, before tracking the element
+        let _: () = con.sadd(&reliable.workers, &reliable.processing).await.unwrap();
+        let _: () = con.rpush(&reliable.processing, "a").await.unwrap();
+        assert_eq!(reliable.requeue_expired(&mut con, key, "RPUSH").await.unwrap(), 0);
+        assert_eq!(reliable.requeue_expired(&mut con, key, "RPUSH").await.unwrap(), 1);
+        let queued: Vec<String> = con.lrange(key, 0, -1).await.unwrap();
+        assert_eq!(queued, vec!["a".to_string()]);
+        clear(&mut con, key, &reliable).await;
+    }
+}
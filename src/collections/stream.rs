@@ -0,0 +1,588 @@
+use anyhow::{Result, Error};
+use async_trait::async_trait;
+use codec::{Encode, Decode};
+use redis::{aio::Connection, AsyncCommands, Script, Value, from_redis_value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use super::{HasAsyncQueue, HasReliableQueue, CodecSerialization, Release};
+use super::reliable::now_millis;
+
+/// stream entry field holding the encoded element
+const ELEMENT_FIELD: &'static str = "element";
+
+/// stream entry field holding the deliveries of the entries the element was requeued from
+const ATTEMPTS_FIELD: &'static str = "attempts";
+
+/// acks entry `id` and adds the element again with its attempts, or moves it to the dead letter list
+/// once it was delivered more than max retries times. fields as `ELEMENT_FIELD` and `ATTEMPTS_FIELD`.
+/// KEYS: stream, dead, dead info. ARGV: group, id, element, attempts, max retries or -1, now, error, max length
+const RELEASE_SCRIPT: &'static str = r#"
+local attempts = tonumber(ARGV[4])
+local max_retries = tonumber(ARGV[5])
+redis.call('XACK', KEYS[1], ARGV[1], ARGV[2])
+if max_retries >= 0 and attempts > max_retries then
+    redis.call('LPUSH', KEYS[2], ARGV[3])
+    redis.call('HSET', KEYS[3], ARGV[3], cjson.encode({
+        error = ARGV[7], attempts = attempts, dead_at = tonumber(ARGV[6]), push = 'XADD',
+    }))
+    return 2
+end
+redis.call('XADD', KEYS[1], 'MAXLEN', '~', ARGV[8], '*', 'element', ARGV[3], 'attempts', attempts)
+return 1
+"#;
+
+/// entries claimed by one XAUTOCLAIM call
+const CLAIM_BATCH_SIZE: usize = 100;
+
+/// entries kept in a stream by default
+const MAX_LEN: usize = 100_000;
+
+/// delivered but not acked stream entry
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub id: String,
+    pub consumer: String,
+    /// milliseconds since the last delivery
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+/// RedisStreamQueue is FIFO Queue data structure by redis streams, consumers of the same group
+/// share the entries, every entry is delivered to one consumer and stays pending until acked.
+/// entries are kept in the stream after ack, so they can be replayed from an id, until the stream
+/// is trimmed to about `max_len` entries
+pub struct RedisStreamQueue<'a> {
+    pub redis_connection: Connection,
+    pub key: &'a str,
+    pub group: String,
+    pub consumer: String,
+    /// pending entries idle longer than this are requeued by `requeue_expired`
+    pub visibility_timeout: Duration,
+    /// elements failing more often go to the `<key>:dead` list, see `DeadLetters`, retried forever if `None`
+    pub max_retries: Option<u32>,
+    /// the oldest entries beyond this are trimmed on push, pending ones included
+    pub max_len: usize,
+    group_created: bool,
+    delivered: Delivered,
+}
+
+impl<'a> RedisStreamQueue<'a> {
+    pub fn new(redis_connection: Connection, key: &'a str, group: &'a str, consumer: &'a str, visibility_timeout: Duration) -> RedisStreamQueue<'a> {
+        RedisStreamQueue {
+            redis_connection,
+            key,
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            visibility_timeout,
+            max_retries: None,
+            max_len: MAX_LEN,
+            group_created: false,
+            delivered: Delivered::default(),
+        }
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// `MAX_LEN` by default
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// pending entries of the group, oldest first
+    pub async fn pending(&mut self, count: usize) -> Result<Vec<PendingEntry>> {
+        self.create_group().await?;
+        let pending: Vec<(String, String, u64, u64)> = redis::cmd("XPENDING")
+            .arg(self.key)
+            .arg(&self.group)
+            .arg("-")
+            .arg("+")
+            .arg(count)
+            .query_async(&mut self.redis_connection)
+            .await?;
+        Ok(pending.into_iter()
+            .map(|(id, consumer, idle, deliveries)| PendingEntry { id, consumer, idle, deliveries })
+            .collect())
+    }
+
+    /// up to `count` entries from id `from` on, whether acked or not
+    pub async fn replay<T: Decode>(&mut self, from: &str, count: usize) -> Result<Vec<(String, Box<T>)>> {
+        let reply: Value = redis::cmd("XRANGE")
+            .arg(self.key)
+            .arg(from)
+            .arg("+")
+            .arg("COUNT")
+            .arg(count)
+            .query_async(&mut self.redis_connection)
+            .await?;
+        entries(&reply)?.into_iter()
+            .filter_map(|entry| entry.element.map(|element| (entry.id, element)))
+            .map(|(id, element)| decode_element(&element).map(|element| (id, element)))
+            .collect()
+    }
+
+    /// create the consumer group and the stream if missing, new groups start at the end of the stream
+    async fn create_group(&mut self) -> Result<()> {
+        if self.group_created {
+            return Ok(());
+        }
+        let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(self.key)
+            .arg(&self.group)
+            .arg("$")
+            .arg("MKSTREAM")
+            .query_async(&mut self.redis_connection)
+            .await;
+        if let Err(e) = created {
+            if e.code() != Some("BUSYGROUP") {
+                return Err(e.into());
+            }
+        }
+        self.group_created = true;
+        Ok(())
+    }
+
+    /// read up to `count` new entries for this consumer, waiting up to `block` if set
+    async fn read(&mut self, count: usize, block: Option<Duration>) -> Result<Vec<Vec<u8>>> {
+        self.create_group().await?;
+        self.delivered.expire(self.visibility_timeout);
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP").arg(&self.group).arg(&self.consumer).arg("COUNT").arg(count);
+        if let Some(block) = block {
+            cmd.arg("BLOCK").arg(block.as_millis() as u64);
+        }
+        let reply: Value = cmd.arg("STREAMS").arg(self.key).arg(">")
+            .query_async(&mut self.redis_connection)
+            .await?;
+        let streams = match reply {
+            Value::Bulk(streams) => streams,
+            _ => return Ok(vec![]),
+        };
+        let stream = match streams.first() {
+            Some(Value::Bulk(stream)) if stream.len() == 2 => &stream[1],
+            _ => return Ok(vec![]),
+        };
+        let mut elements = vec![];
+        for entry in entries(stream)? {
+            if let Some(element) = entry.element {
+                self.delivered.insert(element.clone(), entry.id, entry.attempts);
+                elements.push(element);
+            }
+        }
+        Ok(elements)
+    }
+
+    /// ack the entry `id` delivered after `attempts` earlier deliveries and add `element` again as a new entry,
+    /// or dead letter it
+    async fn release(&mut self, id: &str, element: &[u8], attempts: u32, error: &str) -> Result<Release> {
+        let released: u8 = Script::new(RELEASE_SCRIPT)
+            .key(self.key)
+            .key(format!("{}:dead", self.key))
+            .key(format!("{}:dead:info", self.key))
+            .arg(&self.group)
+            .arg(id)
+            .arg(element)
+            .arg(attempts + 1)
+            .arg(self.max_retries.map(|max| max as i64).unwrap_or(-1))
+            .arg(now_millis())
+            .arg(error)
+            .arg(self.max_len)
+            .invoke_async(&mut self.redis_connection)
+            .await?;
+        let release = Release::from(released);
+        if release == Release::Dead {
+            llog::warn!("{} entry {} moved to {}:dead after too many retries: {}", self.key, id, self.key, error);
+        }
+        Ok(release)
+    }
+}
+
+impl<'a, T: Clone + Encode + Decode> CodecSerialization<T> for RedisStreamQueue<'a> {
+    fn name(&self) -> &'static str {
+        "redis-stream-queue"
+    }
+}
+
+#[async_trait]
+impl<'a, T: Clone + Encode + Decode + Sync + Send> HasAsyncQueue<T> for RedisStreamQueue<'a> {
+    async fn push(&mut self, element: &Box<T>) -> Result<()> {
+        self.create_group().await?;
+        let encode_res: Vec<u8> = self.encode(&element);
+        redis::cmd("XADD").arg(self.key).arg("MAXLEN").arg("~").arg(self.max_len).arg("*").arg(ELEMENT_FIELD).arg(encode_res)
+            .query_async::<_, String>(&mut self.redis_connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn push_many(&mut self, elements: &[Box<T>]) -> Result<()> {
+        if elements.is_empty() {
+            return Ok(());
+        }
+        self.create_group().await?;
+        let mut pipe = redis::pipe();
+        for element in elements {
+            let encode_res: Vec<u8> = self.encode(element);
+            pipe.cmd("XADD").arg(self.key).arg("MAXLEN").arg("~").arg(self.max_len).arg("*").arg(ELEMENT_FIELD).arg(encode_res).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut self.redis_connection).await?;
+        Ok(())
+    }
+
+    async fn pop(&mut self) -> Result<Box<T>> {
+        let pop_res = self.read(1, None).await?.pop().ok_or_else(|| Error::msg("RedisStreamQueue is empty"))?;
+        decode_element(&pop_res)
+    }
+
+    async fn pop_many(&mut self, n: usize) -> Result<Vec<Box<T>>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        self.read(n, None).await?.iter().map(|pop_res| decode_element(pop_res)).collect()
+    }
+
+    async fn pop_blocking(&mut self, timeout: Duration) -> Result<Option<Box<T>>> {
+        match self.read(1, Some(timeout)).await?.pop() {
+            Some(pop_res) => decode_element(&pop_res).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// delete the stream with its consumer groups
+    async fn clear(&mut self) -> Result<()> {
+        self.redis_connection.del(self.key).await?;
+        self.group_created = false;
+        self.delivered.clear();
+        Ok(())
+    }
+
+    /// entries kept in the stream, acked ones included
+    async fn len(&mut self) -> Result<usize> {
+        let res = redis::cmd("XLEN").arg(self.key).query_async(&mut self.redis_connection).await?;
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl<'a, T: Clone + Encode + Decode + Sync + Send> HasReliableQueue<T> for RedisStreamQueue<'a> {
+    async fn ack(&mut self, element: &Box<T>) -> Result<()> {
+        let acked: usize = match self.delivered.take(&element.encode()) {
+            Some(delivery) => redis::cmd("XACK").arg(self.key).arg(&self.group).arg(delivery.id)
+                .query_async(&mut self.redis_connection)
+                .await?,
+            None => 0,
+        };
+        if acked == 0 {
+            llog::warn!("RedisStreamQueue {} acked element was already requeued", self.key);
+        }
+        Ok(())
+    }
+
+    /// requeue as a new entry at the end of the stream, or dead letter it once it ran out of retries
+    async fn nack(&mut self, element: &Box<T>, error: &str) -> Result<Release> {
+        let encoded = element.encode();
+        let delivery = match self.delivered.take(&encoded) {
+            Some(delivery) => delivery,
+            None => return Ok(Release::Missing),
+        };
+        llog::warn!("{} entry {} failed: {}", self.key, delivery.id, error);
+        self.release(&delivery.id, &encoded, delivery.attempts, error).await
+    }
+
+    /// claim entries idle longer than the visibility timeout from any consumer and requeue them,
+    /// returns how many were requeued rather than dead lettered
+    async fn requeue_expired(&mut self) -> Result<usize> {
+        self.create_group().await?;
+        self.delivered.expire(self.visibility_timeout);
+        let mut start = "0-0".to_string();
+        let mut count = 0;
+        loop {
+            let reply: Value = redis::cmd("XAUTOCLAIM")
+                .arg(self.key)
+                .arg(&self.group)
+                .arg(&self.consumer)
+                .arg(self.visibility_timeout.as_millis() as u64)
+                .arg(&start)
+                .arg("COUNT")
+                .arg(CLAIM_BATCH_SIZE)
+                .query_async(&mut self.redis_connection)
+                .await?;
+            let reply = match reply {
+                Value::Bulk(reply) if reply.len() >= 2 => reply,
+                _ => return Err(Error::msg("RedisStreamQueue unexpected XAUTOCLAIM reply")),
+            };
+            let claimed = entries(&reply[1])?;
+            // claimed entries of this consumer are requeued below, acking them later would fail
+            self.delivered.evict(&claimed.iter().map(|entry| entry.id.clone()).collect());
+            for entry in claimed {
+                match entry.element {
+                    Some(element) => {
+                        if self.release(&entry.id, &element, entry.attempts, "visibility timeout expired").await? == Release::Requeued {
+                            count += 1;
+                        }
+                    }
+                    // trimmed from the stream
+                    None => {
+                        redis::cmd("XACK").arg(self.key).arg(&self.group).arg(&entry.id)
+                            .query_async::<_, ()>(&mut self.redis_connection)
+                            .await?;
+                    }
+                }
+            }
+            start = from_redis_value(&reply[0])?;
+            if start == "0-0" {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+struct Delivery {
+    id: String,
+    /// deliveries before the element was added again as this entry
+    attempts: u32,
+    at: Instant,
+}
+
+/// entries delivered to this consumer and not acked yet by encoded element, to ack by element
+#[derive(Default)]
+struct Delivered {
+    entries: HashMap<Vec<u8>, VecDeque<Delivery>>,
+}
+
+impl Delivered {
+    fn insert(&mut self, element: Vec<u8>, id: String, attempts: u32) {
+        self.entries.entry(element).or_default().push_back(Delivery { id, attempts, at: Instant::now() });
+    }
+
+    /// oldest delivery of `element`
+    fn take(&mut self, element: &[u8]) -> Option<Delivery> {
+        let deliveries = self.entries.get_mut(element)?;
+        let delivery = deliveries.pop_front();
+        if deliveries.is_empty() {
+            self.entries.remove(element);
+        }
+        delivery
+    }
+
+    /// forget the deliveries of entries `ids`
+    fn evict(&mut self, ids: &HashSet<String>) {
+        self.retain(|delivery| !ids.contains(&delivery.id));
+    }
+
+    /// forget deliveries older than `timeout`, any consumer may have claimed them since
+    fn expire(&mut self, timeout: Duration) {
+        self.retain(|delivery| delivery.at.elapsed() < timeout);
+    }
+
+    fn retain(&mut self, keep: impl Fn(&Delivery) -> bool) {
+        self.entries.retain(|_, deliveries| {
+            deliveries.retain(|delivery| keep(delivery));
+            !deliveries.is_empty()
+        });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+struct Entry {
+    id: String,
+    /// `None` for entries deleted from the stream
+    element: Option<Vec<u8>>,
+    /// deliveries before the element was added again as this entry
+    attempts: u32,
+}
+
+/// `[id, [field, value, ..]]` entries of a stream reply
+fn entries(reply: &Value) -> Result<Vec<Entry>> {
+    let entries = match reply {
+        Value::Bulk(entries) => entries,
+        Value::Nil => return Ok(vec![]),
+        _ => return Err(Error::msg("RedisStreamQueue unexpected stream reply")),
+    };
+    let mut res = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let entry = match entry {
+            Value::Bulk(entry) if entry.len() == 2 => entry,
+            _ => return Err(Error::msg("RedisStreamQueue unexpected stream entry")),
+        };
+        let id: String = from_redis_value(&entry[0])?;
+        let fields: &[Value] = match &entry[1] {
+            Value::Bulk(fields) => fields,
+            _ => &[],
+        };
+        let field = |name: &str| fields.chunks(2)
+            .find(|field| field.len() == 2 && from_redis_value::<String>(&field[0]).ok().as_deref() == Some(name))
+            .map(|field| &field[1]);
+        let element = field(ELEMENT_FIELD).map(from_redis_value::<Vec<u8>>).transpose()?;
+        let attempts = field(ATTEMPTS_FIELD).map(from_redis_value::<u32>).transpose()?.unwrap_or(0);
+        res.push(Entry { id, element, attempts });
+    }
+    Ok(res)
+}
+
+fn decode_element<T: Decode>(encoded: &[u8]) -> Result<Box<T>> {
+    let mut encode_res: &[u8] = encoded;
+    Box::<T>::decode(&mut encode_res).map_err(|_| Error::msg("RedisStreamQueue pop decode error"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::DeadLetters;
+    use crate::collections::tests::test_connection;
+
+    fn data(value: &str) -> Value {
+        Value::Data(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn parse_entries() {
+        let reply = Value::Bulk(vec![
+            Value::Bulk(vec![data("1-0"), Value::Bulk(vec![data(ELEMENT_FIELD), data("a")])]),
+            Value::Bulk(vec![data("2-0"), Value::Bulk(vec![data(ELEMENT_FIELD), data("b"), data(ATTEMPTS_FIELD), data("3")])]),
+            // deleted from the stream
+            Value::Bulk(vec![data("3-0"), Value::Nil]),
+        ]);
+        let entries = entries(&reply).unwrap();
+        let parsed: Vec<_> = entries.iter().map(|entry| (entry.id.as_str(), entry.element.as_deref(), entry.attempts)).collect();
+        assert_eq!(parsed, vec![
+            ("1-0", Some(&b"a"[..]), 0),
+            ("2-0", Some(&b"b"[..]), 3),
+            ("3-0", None, 0),
+        ]);
+        assert!(super::entries(&Value::Nil).unwrap().is_empty());
+        assert!(super::entries(&Value::Int(1)).is_err());
+    }
+
+    #[test]
+    fn delivered_entries_are_taken_in_order_and_evicted() {
+        let mut delivered = Delivered::default();
+        delivered.insert(b"a".to_vec(), "1-0".into(), 0);
+        delivered.insert(b"a".to_vec(), "2-0".into(), 2);
+        delivered.insert(b"b".to_vec(), "3-0".into(), 0);
+        let first = delivered.take(b"a").unwrap();
+        assert_eq!((first.id.as_str(), first.attempts), ("1-0", 0));
+
+        delivered.evict(&vec!["2-0".to_string()].into_iter().collect());
+        assert!(delivered.take(b"a").is_none());
+        assert!(!delivered.entries.contains_key(&b"a"[..]));
+
+        delivered.expire(Duration::from_secs(60));
+        assert_eq!(delivered.entries.len(), 1);
+        delivered.expire(Duration::from_secs(0));
+        assert!(delivered.take(b"b").is_none());
+        assert!(delivered.entries.is_empty());
+    }
+
+    async fn queue<'a>(key: &'a str, consumer: &'a str, visibility_timeout: Duration) -> Option<RedisStreamQueue<'a>> {
+        let con = test_connection().await?;
+        Some(RedisStreamQueue::new(con, key, "test", consumer, visibility_timeout).max_retries(1))
+    }
+
+    async fn clear(key: &str) {
+        let mut con = test_connection().await.unwrap();
+        let _: () = con.del(vec![key.to_string(), format!("{}:dead", key), format!("{}:dead:info", key)]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delivers_and_acks() {
+        let key = "explorer:test:stream:ack";
+        let mut queue = match queue(key, "first", Duration::from_secs(60)).await {
+            Some(queue) => queue,
+            None => return,
+        };
+        clear(key).await;
+        assert!(HasAsyncQueue::<u32>::pop_blocking(&mut queue, Duration::from_millis(10)).await.unwrap().is_none());
+        queue.push_many(&[Box::new(1u32), Box::new(2)]).await.unwrap();
+        queue.push(&Box::new(3u32)).await.unwrap();
+
+        let popped: Vec<Box<u32>> = queue.pop_many(2).await.unwrap();
+        let last: Box<u32> = queue.pop().await.unwrap();
+        assert_eq!(popped, vec![Box::new(1), Box::new(2)]);
+        assert_eq!(*last, 3);
+        let pending = queue.pending(10).await.unwrap();
+        assert_eq!(pending.len(), 3);
+        assert!(pending.iter().all(|entry| entry.consumer == "first" && entry.deliveries == 1 && entry.idle < 60_000));
+
+        for element in popped.iter().chain(Some(&last)) {
+            queue.ack(element).await.unwrap();
+        }
+        assert!(queue.pending(10).await.unwrap().is_empty());
+        assert!(queue.delivered.entries.is_empty());
+        let replayed: Vec<(String, Box<u32>)> = queue.replay(&pending[0].id, 10).await.unwrap();
+        assert_eq!(replayed.len(), 3);
+        assert_eq!(HasAsyncQueue::<u32>::len(&mut queue).await.unwrap(), 3);
+        HasAsyncQueue::<u32>::clear(&mut queue).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_letters_after_max_retries() {
+        let key = "explorer:test:stream:dead";
+        let mut queue = match queue(key, "first", Duration::from_secs(60)).await {
+            Some(queue) => queue,
+            None => return,
+        };
+        clear(key).await;
+        queue.create_group().await.unwrap();
+        queue.push(&Box::new(7u32)).await.unwrap();
+
+        let element: Box<u32> = queue.pop().await.unwrap();
+        assert_eq!(queue.nack(&element, "first failure").await.unwrap(), Release::Requeued);
+        let element: Box<u32> = queue.pop().await.unwrap();
+        assert_eq!(queue.nack(&element, "second failure").await.unwrap(), Release::Dead);
+        // not delivered anymore
+        assert_eq!(queue.nack(&element, "third failure").await.unwrap(), Release::Missing);
+
+        let mut dead_letters = DeadLetters::<u32>::new(test_connection().await.unwrap(), key);
+        let letter = dead_letters.get(0).await.unwrap().unwrap();
+        assert_eq!((*letter.element, letter.info.attempts, letter.info.error.as_str()), (7, 2, "second failure"));
+        assert!(dead_letters.requeue(0).await.unwrap());
+        let element: Box<u32> = queue.pop().await.unwrap();
+        assert_eq!(*element, 7);
+        queue.ack(&element).await.unwrap();
+        clear(key).await;
+    }
+
+    #[tokio::test]
+    async fn requeues_expired_entries_of_other_consumers() {
+        let key = "explorer:test:stream:expired";
+        let (mut first, mut second) = match (queue(key, "first", Duration::from_secs(0)).await, queue(key, "second", Duration::from_secs(0)).await) {
+            (Some(first), Some(second)) => (first, second),
+            _ => return,
+        };
+        clear(key).await;
+        first.create_group().await.unwrap();
+        first.push(&Box::new(1u32)).await.unwrap();
+        let element: Box<u32> = first.pop().await.unwrap();
+
+        assert_eq!(HasReliableQueue::<u32>::requeue_expired(&mut second).await.unwrap(), 1);
+        // the claimed entry is gone, acking it only warns
+        first.ack(&element).await.unwrap();
+        assert!(first.delivered.entries.is_empty());
+        let element: Box<u32> = second.pop().await.unwrap();
+        assert_eq!(*element, 1);
+        assert_eq!(second.pending(10).await.unwrap()[0].consumer, "second");
+        clear(key).await;
+    }
+
+    #[tokio::test]
+    async fn trims_the_stream() {
+        let key = "explorer:test:stream:trim";
+        let mut queue = match queue(key, "first", Duration::from_secs(60)).await {
+            Some(queue) => queue.max_len(10),
+            None => return,
+        };
+        clear(key).await;
+        let elements: Vec<Box<u32>> = (0..1000).map(Box::new).collect();
+        queue.push_many(&elements).await.unwrap();
+        // approximate trimming keeps whole radix tree nodes
+        assert!(HasAsyncQueue::<u32>::len(&mut queue).await.unwrap() < 1000);
+        clear(key).await;
+    }
+}
@@ -7,6 +7,8 @@ use redis::Client as RedisClient;
 use meilisearch_sdk::client::Client;
 use substrate_subxt::{Runtime, ClientBuilder, Client as SubClient};
 use std::time::Duration;
+use std::collections::HashMap;
+use crate::runtime::RUNTIME_NAME;
 
 pub const REDIS_TIMEOUT: Duration = Duration::from_secs(3);
 
@@ -16,6 +18,84 @@ pub const CELERY_HEARTBEAT: Option<u16> = Some(10);
 
 pub const CONFIG_FILE: &'static str = "explorer.toml";
 
+pub const CHECKPOINT_KEY: &'static str = "explorer:checkpoint";
+
+/// max blocks dispatched by a single pull
+pub const PULL_BATCH_SIZE: u64 = 1000;
+
+/// blocks in a block chunk
+pub const DECODE_CHUNK_SIZE: u64 = 10;
+
+/// block chunks decoded by a single decode_block task
+pub const CHUNKS_PER_TASK: usize = 10;
+
+pub const UNFINALIZED_KEY: &'static str = "explorer:unfinalized";
+
+/// max documents in a single meilisearch write
+pub const INDEX_BATCH_SIZE: usize = 1000;
+
+pub const UPDATE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// max wait for meilisearch to process the updates of a write
+pub const UPDATE_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub const FILTER_KEY: &'static str = "explorer:filter";
+
+pub const METADATA_KEY: &'static str = "explorer:metadata";
+
+/// runtime metadata versions kept in memory by a decoder
+pub const METADATA_CACHE_SIZE: usize = 16;
+
+pub const BLOCK_QUEUE_KEY: &'static str = "explorer:blocks";
+
+/// block chunks pushed to the block queue in one round trip
+pub const PUSH_BATCH_SIZE: usize = 1000;
+
+/// priority of tip-following block chunks, lower score pops first
+pub const TIP_PRIORITY: i32 = 0;
+
+/// default priority of backfill block chunks
+pub const BACKFILL_PRIORITY: i32 = 100;
+
+/// tip chunks not completed within this are dispatched again, unless they still wait in the block queue
+/// or the retry queue, or are being decoded
+pub const DISPATCH_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// tracked tip chunks above which pull stops dispatching new blocks until consumers catch up
+pub const MAX_INFLIGHT_CHUNKS: usize = 500;
+
+pub const PULL_LOCK_KEY: &'static str = "explorer:pull";
+
+/// a pull holding the lock longer is assumed dead
+pub const PULL_LOCK_TTL: Duration = Duration::from_secs(300);
+
+/// last active block of every account by chain, the source of truth merged into the accounts index
+pub const ACCOUNTS_KEY: &'static str = "explorer:accounts";
+
+/// held while merging accounts and enqueueing them, so meilisearch applies the merged values in order
+pub const ACCOUNTS_LOCK_TTL: Duration = Duration::from_secs(60);
+
+/// how long the previous activity of accounts moved by a non-finalized block is kept to roll it back
+pub const ACCOUNTS_UNDO_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// how long a decode_block task waits for its chunk, e.g. when a retry already took it
+pub const POP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// block chunks not acked within this are delivered again
+pub const VISIBILITY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// failed block chunks waiting for a retry, promoted back to the block queue by consumers
+pub const BLOCK_RETRY_KEY: &'static str = "explorer:blocks:retry";
+
+/// retry delay of a block chunk after its first failure, doubled on every further failure
+pub const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// longest retry delay, tip chunks waiting for their retry are not dispatched again by pull
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// how often consumers look for due block chunk retries
+pub const RETRY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 
 pub struct AppState<'a> {
     pub meili_client: Client<'a>,
@@ -50,11 +130,139 @@ impl AppState<'_> {
 pub struct MeiliSearch {
     pub host: String,
     pub apikey: String,
+    /// index settings by index uid, unset fields keep the defaults of `db::settings`
+    #[serde(default)]
+    pub indexes: HashMap<String, IndexSettings>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct IndexSettings {
+    pub searchable_attributes: Option<Vec<String>>,
+    pub filterable_attributes: Option<Vec<String>>,
+    /// not supported by the pinned meilisearch, ignored with a warning
+    pub sortable_attributes: Option<Vec<String>>,
+    pub ranking_rules: Option<Vec<String>>,
+    pub synonyms: Option<HashMap<String, Vec<String>>>,
+    pub stop_words: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Chain {
+    /// redis key namespace of the chain, the compiled runtime name by default
+    #[serde(default = "default_chain_name")]
+    pub name: String,
     pub rpc_url: String,
+    /// also index blocks above the finalized head and roll them back on reorg
+    #[serde(default)]
+    pub follow_best_head: bool,
+    /// decode with the compile-time runtime or from runtime metadata
+    #[serde(default)]
+    pub decoder: DecoderMode,
+    /// type definitions for the dynamic decoder, e.g. `Address = "AccountId"`
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+}
+
+fn default_chain_name() -> String {
+    RUNTIME_NAME.to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DecoderMode {
+    Static,
+    Dynamic,
+}
+
+impl Default for DecoderMode {
+    fn default() -> Self {
+        DecoderMode::Static
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    Meilisearch,
+    File,
+    Sqlite,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Sink {
+    pub kind: SinkKind,
+    /// directory of jsonl files for `file`, database file for `sqlite`
+    #[serde(default)]
+    pub path: String,
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink {
+            kind: SinkKind::Meilisearch,
+            path: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    /// bloom filter in a redis bitmap, shared by all consumers
+    Bloom,
+    /// exact fingerprints in process memory
+    Memory,
+    /// exact fingerprints in redis, forgotten after `window` seconds
+    Window,
+}
+
+impl Default for FilterKind {
+    fn default() -> Self {
+        FilterKind::Bloom
+    }
+}
+
+/// duplicate filter of processed block hashes, extrinsic hashes are not fingerprinted.
+/// a hit is confirmed by the sink, except the file sink which loses blocks at `error_rate` with bloom
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// expected number of fingerprinted items, the default bloom filter takes a ~180MB bitmap
+    pub capacity: u64,
+    /// false positive rate at capacity
+    pub error_rate: f64,
+    /// seconds the window filter remembers an item
+    pub window: u64,
+    /// buckets the window is split into
+    pub buckets: u64,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            kind: FilterKind::Bloom,
+            capacity: 100_000_000,
+            error_rate: 0.001,
+            window: 3600,
+            buckets: 12,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Queue {
+    /// block chunks failing after this many redeliveries go to the dead letter list
+    pub max_retries: u32,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Queue {
+            max_retries: 5,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -82,6 +290,12 @@ pub struct Settings {
     pub meilisearch: MeiliSearch,
     pub chain: Chain,
     pub redis: Redis,
+    #[serde(default)]
+    pub sink: Sink,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub queue: Queue,
 }
 
 impl Settings {
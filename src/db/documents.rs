@@ -0,0 +1,253 @@
+use meilisearch_sdk::document::Document;
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::decoder::{DecodedBlock, DecodedExtrinsic, DecodedEvent};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDocument {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub finalized: bool,
+    pub spec_version: Option<u32>,
+    pub timestamp: Option<u64>,
+    pub extrinsics_count: u32,
+    pub events_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtrinsicDocument {
+    /// `{block_number}-{index}`
+    pub id: String,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub index: u32,
+    pub hash: String,
+    pub signed: bool,
+    pub module: Option<String>,
+    pub call: Option<String>,
+    pub signer: Option<String>,
+    pub args: Option<Value>,
+    pub success: Option<bool>,
+    pub finalized: bool,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventDocument {
+    /// `{block_number}-{index}`
+    pub id: String,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub index: u32,
+    pub extrinsic_index: Option<u32>,
+    pub module: String,
+    pub variant: String,
+    pub args: Option<Value>,
+    pub finalized: bool,
+    pub data: String,
+}
+
+/// AccountDocument is partially updated, so only fields known from a single block are stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountDocument {
+    pub id: String,
+    pub last_active_block: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferDocument {
+    /// `{block_number}-{event_index}`
+    pub id: String,
+    pub block_number: u64,
+    pub extrinsic_index: Option<u32>,
+    pub from: String,
+    pub to: String,
+    pub amount: String,
+    pub finalized: bool,
+}
+
+impl Document for BlockDocument {
+    type UIDType = u64;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.number
+    }
+}
+
+impl Document for ExtrinsicDocument {
+    type UIDType = String;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.id
+    }
+}
+
+impl Document for EventDocument {
+    type UIDType = String;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.id
+    }
+}
+
+impl Document for AccountDocument {
+    type UIDType = String;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.id
+    }
+}
+
+impl Document for TransferDocument {
+    type UIDType = String;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.id
+    }
+}
+
+/// id of an extrinsic or event document
+pub fn document_id(block_number: u64, index: u32) -> String {
+    format!("{}-{}", block_number, index)
+}
+
+/// Documents are the meilisearch documents of decoded blocks
+#[derive(Debug, Default)]
+pub struct Documents {
+    pub blocks: Vec<BlockDocument>,
+    pub extrinsics: Vec<ExtrinsicDocument>,
+    pub events: Vec<EventDocument>,
+    pub accounts: Vec<AccountDocument>,
+    pub transfers: Vec<TransferDocument>,
+}
+
+impl Documents {
+    pub fn from_blocks(blocks: &[DecodedBlock]) -> Documents {
+        let mut documents = Documents::default();
+        let mut accounts: HashMap<String, u64> = HashMap::new();
+
+        for block in blocks {
+            documents.blocks.push(BlockDocument {
+                number: block.number,
+                hash: block.hash.clone(),
+                parent_hash: block.parent_hash.clone(),
+                finalized: block.finalized,
+                spec_version: block.spec_version,
+                timestamp: timestamp(&block.extrinsics),
+                extrinsics_count: block.extrinsics.len() as u32,
+                events_count: block.events.len() as u32,
+            });
+
+            for extrinsic in block.extrinsics.iter() {
+                if let Some(signer) = &extrinsic.signer {
+                    active(&mut accounts, signer, block.number);
+                }
+                documents.extrinsics.push(ExtrinsicDocument {
+                    id: document_id(block.number, extrinsic.index),
+                    block_number: block.number,
+                    block_hash: block.hash.clone(),
+                    index: extrinsic.index,
+                    hash: extrinsic.hash.clone(),
+                    signed: extrinsic.signed,
+                    module: extrinsic.module.clone(),
+                    call: extrinsic.call.clone(),
+                    signer: extrinsic.signer.clone(),
+                    args: extrinsic.args.clone(),
+                    success: success(&block.events, extrinsic.index),
+                    finalized: block.finalized,
+                    data: extrinsic.data.clone(),
+                });
+            }
+
+            for event in block.events.iter() {
+                if let Some(transfer) = transfer(block, event) {
+                    active(&mut accounts, &transfer.from, block.number);
+                    active(&mut accounts, &transfer.to, block.number);
+                    documents.transfers.push(transfer);
+                }
+                documents.events.push(EventDocument {
+                    id: document_id(block.number, event.index),
+                    block_number: block.number,
+                    block_hash: block.hash.clone(),
+                    index: event.index,
+                    extrinsic_index: event.extrinsic_index,
+                    module: event.module.clone(),
+                    variant: event.variant.clone(),
+                    args: event.args.clone(),
+                    finalized: block.finalized,
+                    data: event.data.clone(),
+                });
+            }
+        }
+
+        documents.accounts = accounts.into_iter()
+            .map(|(id, last_active_block)| AccountDocument { id, last_active_block })
+            .collect();
+        documents
+    }
+}
+
+fn active(accounts: &mut HashMap<String, u64>, account: &str, block_number: u64) {
+    let last = accounts.entry(account.to_string()).or_insert(block_number);
+    *last = (*last).max(block_number);
+}
+
+/// block timestamp set by the `Timestamp.set` inherent
+fn timestamp(extrinsics: &[DecodedExtrinsic]) -> Option<u64> {
+    extrinsics.iter()
+        .find(|extrinsic| extrinsic.module.as_deref() == Some("Timestamp") && extrinsic.call.as_deref() == Some("set"))
+        .and_then(|extrinsic| extrinsic.args.as_ref())
+        .and_then(|args| args["now"].as_u64())
+}
+
+/// extrinsic result from `System.ExtrinsicSuccess` / `System.ExtrinsicFailed` events
+fn success(events: &[DecodedEvent], extrinsic_index: u32) -> Option<bool> {
+    events.iter()
+        .filter(|event| event.extrinsic_index == Some(extrinsic_index) && event.module == "System")
+        .find_map(|event| match event.variant.as_str() {
+            "ExtrinsicSuccess" => Some(true),
+            "ExtrinsicFailed" => Some(false),
+            _ => None,
+        })
+}
+
+/// transfer from a decoded `Balances.Transfer(from, to, amount)` event
+fn transfer(block: &DecodedBlock, event: &DecodedEvent) -> Option<TransferDocument> {
+    if event.module != "Balances" || event.variant != "Transfer" {
+        return None;
+    }
+    let args = event.args.as_ref()?.as_array()?;
+    if args.len() != 3 {
+        return None;
+    }
+    let amount = match &args[2] {
+        Value::String(amount) => amount.clone(),
+        amount => amount.to_string(),
+    };
+    Some(TransferDocument {
+        id: document_id(block.number, event.index),
+        block_number: block.number,
+        extrinsic_index: event.extrinsic_index,
+        from: args[0].as_str()?.to_string(),
+        to: args[1].as_str()?.to_string(),
+        amount,
+        finalized: block.finalized,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::block;
+
+    #[test]
+    fn accounts_keep_latest_block() {
+        let documents = Documents::from_blocks(&[block(7, "0x01"), block(3, "0x01")]);
+        let mut accounts = documents.accounts.clone();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(accounts.iter().map(|a| (a.id.as_str(), a.last_active_block)).collect::<Vec<_>>(),
+                   vec![("0x01", 7), ("0x02", 7)]);
+        assert_eq!(documents.transfers.len(), 2);
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{Result, Error};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::decoder::DecodedBlock;
+use super::Sink;
+use super::documents::Documents;
+
+/// FileSink appends documents as newline-delimited json, one file per document type
+pub struct FileSink {
+    pub dir: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(dir: &str) -> FileSink {
+        FileSink {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn init(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    async fn write(&self, blocks: &[DecodedBlock]) -> Result<()> {
+        let documents = Documents::from_blocks(blocks);
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || {
+            append(&dir.join("blocks.jsonl"), &documents.blocks)?;
+            append(&dir.join("extrinsics.jsonl"), &documents.extrinsics)?;
+            append(&dir.join("events.jsonl"), &documents.events)?;
+            append(&dir.join("transfers.jsonl"), &documents.transfers)?;
+            // appended per write, the latest activity of an account is its highest `last_active_block`
+            append(&dir.join("accounts.jsonl"), &documents.accounts)
+        }).await.map_err(|e| Error::msg(e.to_string()))?
+    }
+
+    /// files are append only, removed blocks are recorded in `removed.jsonl`
+    async fn remove(&self, numbers: &[u64]) -> Result<()> {
+        let numbers = numbers.to_vec();
+        let path = self.dir.join("removed.jsonl");
+        tokio::task::spawn_blocking(move || append(&path, &numbers))
+            .await.map_err(|e| Error::msg(e.to_string()))?
+    }
+
+    /// files are not read back, a duplicate filter hit is trusted
+    async fn contains(&self, _number: u64, _hash: &str) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+fn append<T: Serialize>(path: &Path, documents: &[T]) -> Result<()> {
+    if documents.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for document in documents {
+        lines.push_str(&serde_json::to_string(document)?);
+        lines.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(lines.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::block;
+
+    #[tokio::test]
+    async fn writes_all_documents() {
+        let dir = std::env::temp_dir().join(format!("explorer-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let sink = FileSink::new(dir.to_str().unwrap());
+        sink.init().await.unwrap();
+
+        sink.write(&[block(7, "0x01")]).await.unwrap();
+        for file in ["blocks", "extrinsics", "events", "transfers", "accounts"].iter() {
+            let lines = fs::read_to_string(dir.join(format!("{}.jsonl", file))).unwrap();
+            assert_eq!(lines.lines().count(), if *file == "accounts" { 2 } else { 1 }, "{}.jsonl", file);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
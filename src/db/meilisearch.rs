@@ -0,0 +1,346 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::document::Document;
+use meilisearch_sdk::indexes::Index;
+use redis::{aio::Connection, Client as RedisClient, Script};
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::config::{ACCOUNTS_KEY, ACCOUNTS_LOCK_TTL, ACCOUNTS_UNDO_TTL, INDEX_BATCH_SIZE, IndexSettings, Settings};
+use crate::decoder::DecodedBlock;
+use crate::lock::RedisLock;
+use super::{Sink, settings, BLOCKS_INDEX, EXTRINSICS_INDEX, EVENTS_INDEX, ACCOUNTS_INDEX, TRANSFERS_INDEX, INDEXES};
+use super::documents::{AccountDocument, BlockDocument, Documents, document_id};
+use super::update::UpdateTracker;
+
+/// keeps the later activity of every account, blocks are not written in order.
+/// the previous activity of accounts moved by a non-finalized block is kept in `{accounts}:undo:{block}`
+/// until the block is finalized or removed.
+/// KEYS: accounts. ARGV: undo ttl in seconds, then account, last active block, finalized triples.
+/// returns the merged block of every account
+const MERGE_ACCOUNTS_SCRIPT: &'static str = r"
+local merged = {}
+for i = 2, #ARGV, 3 do
+    local block = tonumber(ARGV[i + 1])
+    local stored = tonumber(redis.call('HGET', KEYS[1], ARGV[i]) or '-1')
+    if block > stored then
+        if ARGV[i + 2] == '0' then
+            local undo = KEYS[1] .. ':undo:' .. ARGV[i + 1]
+            redis.call('HSETNX', undo, ARGV[i], stored)
+            redis.call('EXPIRE', undo, ARGV[1])
+        end
+        redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+        stored = block
+    end
+    merged[#merged + 1] = stored
+end
+return merged
+";
+
+/// restores the activity accounts had before removed blocks, newest block first.
+/// KEYS: accounts. ARGV: removed block numbers in descending order.
+/// returns account, restored block pairs, -1 if the account has no activity left
+const ROLLBACK_ACCOUNTS_SCRIPT: &'static str = r"
+local restored = {}
+for _, number in ipairs(ARGV) do
+    local undo = KEYS[1] .. ':undo:' .. number
+    local previous = redis.call('HGETALL', undo)
+    for i = 1, #previous, 2 do
+        if redis.call('HGET', KEYS[1], previous[i]) == number then
+            if previous[i + 1] == '-1' then
+                redis.call('HDEL', KEYS[1], previous[i])
+            else
+                redis.call('HSET', KEYS[1], previous[i], previous[i + 1])
+            end
+            restored[previous[i]] = previous[i + 1]
+        end
+    end
+    redis.call('DEL', undo)
+end
+local result = {}
+for account, block in pairs(restored) do
+    result[#result + 1] = account
+    result[#result + 1] = block
+end
+return result
+";
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// MeiliSink writes documents of decoded blocks to meilisearch indexes
+pub struct MeiliSink<'a> {
+    pub client: &'a Client<'a>,
+    pub redis_client: RedisClient,
+    /// redis hash of the last active block by account
+    pub accounts_key: String,
+    pub indexes: HashMap<String, IndexSettings>,
+}
+
+impl<'a> MeiliSink<'a> {
+    pub fn new(client: &'a Client<'a>, redis_client: &RedisClient, settings: &Settings) -> MeiliSink<'a> {
+        MeiliSink {
+            client,
+            redis_client: redis_client.clone(),
+            accounts_key: format!("{}:{}", ACCOUNTS_KEY, settings.chain.name),
+            indexes: settings.meilisearch.indexes.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> Sink for MeiliSink<'a> {
+    fn name(&self) -> &'static str {
+        "meilisearch"
+    }
+
+    /// create missing indexes with their primary keys and apply index settings
+    async fn init(&self) -> Result<()> {
+        for (uid, primary_key) in INDEXES.iter() {
+            if self.client.get_index(uid).await.is_err() {
+                self.client.create_index(uid, Some(primary_key)).await?;
+                llog::info!("meilisearch index {} created", uid);
+            }
+        }
+        settings::apply(self.client, &self.indexes).await
+    }
+
+    /// returns once meilisearch has processed all documents
+    async fn write(&self, blocks: &[DecodedBlock]) -> Result<()> {
+        index_blocks(self.client, &self.redis_client, &self.accounts_key, blocks).await
+    }
+
+    async fn remove(&self, numbers: &[u64]) -> Result<()> {
+        remove_blocks(self.client, numbers).await?;
+        rollback_accounts(self.client, &self.redis_client, &self.accounts_key, numbers).await
+    }
+
+    /// a block that cannot be fetched counts as missing, indexing it again is idempotent
+    async fn contains(&self, number: u64, hash: &str) -> Result<bool> {
+        let blocks_index = self.client.get_index(BLOCKS_INDEX).await?;
+        Ok(blocks_index.get_document::<BlockDocument>(number).await.map_or(false, |block| block.hash == hash))
+    }
+}
+
+async fn index_blocks(client: &Client<'_>, redis_client: &RedisClient, accounts_key: &str, blocks: &[DecodedBlock]) -> Result<()> {
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    let mut documents = Documents::from_blocks(blocks);
+    let blocks_index = client.get_index(BLOCKS_INDEX).await?;
+    let extrinsics_index = client.get_index(EXTRINSICS_INDEX).await?;
+    let events_index = client.get_index(EVENTS_INDEX).await?;
+    let transfers_index = client.get_index(TRANSFERS_INDEX).await?;
+    let accounts_index = client.get_index(ACCOUNTS_INDEX).await?;
+
+    let mut updates = UpdateTracker::new();
+    add_or_replace(&mut updates, &blocks_index, &documents.blocks, "number").await?;
+    add_or_replace(&mut updates, &extrinsics_index, &documents.extrinsics, "id").await?;
+    add_or_replace(&mut updates, &events_index, &documents.events, "id").await?;
+    add_or_replace(&mut updates, &transfers_index, &documents.transfers, "id").await?;
+
+    // meilisearch applies updates in the order they are enqueued, so merging and enqueueing
+    // under one lock leaves every account with its latest activity
+    let mut redis_con = redis_client.get_async_connection().await?;
+    let lock = lock_accounts(&mut redis_con, accounts_key).await?;
+    let unfinalized: Vec<u64> = blocks.iter().filter(|block| !block.finalized).map(|block| block.number).collect();
+    let enqueued = add_accounts(&mut updates, &accounts_index, &mut redis_con, accounts_key, &unfinalized, &mut documents.accounts).await;
+    if !lock.release(&mut redis_con).await? {
+        llog::warn!("accounts lock {} expired while enqueueing accounts", lock_key(accounts_key));
+    }
+    enqueued?;
+    updates.wait().await
+}
+
+fn lock_key(accounts_key: &str) -> String {
+    format!("{}:lock", accounts_key)
+}
+
+/// wait for the accounts lock, it expires if its owner died
+async fn lock_accounts(con: &mut Connection, accounts_key: &str) -> Result<RedisLock> {
+    loop {
+        if let Some(lock) = RedisLock::acquire(con, &lock_key(accounts_key), ACCOUNTS_LOCK_TTL).await? {
+            return Ok(lock);
+        }
+        tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+    }
+}
+
+async fn add_accounts<'a>(updates: &mut UpdateTracker<'a>, index: &'a Index<'a>, con: &mut Connection, accounts_key: &str,
+                          unfinalized: &[u64], accounts: &mut [AccountDocument]) -> Result<()> {
+    merge_accounts(con, accounts_key, unfinalized, accounts).await?;
+    for batch in accounts.chunks(INDEX_BATCH_SIZE) {
+        updates.track(ACCOUNTS_INDEX, index.add_or_update(batch, Some("id")).await?);
+    }
+    Ok(())
+}
+
+/// merge accounts with the stored ones in one call, so backfilled blocks do not move their activity backwards.
+/// accounts last active in one of the `unfinalized` blocks can be rolled back with the block
+async fn merge_accounts(con: &mut Connection, accounts_key: &str, unfinalized: &[u64], accounts: &mut [AccountDocument]) -> Result<()> {
+    if accounts.is_empty() {
+        return Ok(());
+    }
+    let script = Script::new(MERGE_ACCOUNTS_SCRIPT);
+    let mut invocation = script.prepare_invoke();
+    invocation.key(accounts_key).arg(ACCOUNTS_UNDO_TTL.as_secs());
+    for account in accounts.iter() {
+        let finalized = !unfinalized.contains(&account.last_active_block);
+        invocation.arg(&account.id).arg(account.last_active_block).arg(finalized as u8);
+    }
+    let merged: Vec<u64> = invocation.invoke_async(con).await?;
+    for (account, last_active_block) in accounts.iter_mut().zip(merged) {
+        account.last_active_block = last_active_block;
+    }
+    Ok(())
+}
+
+async fn add_or_replace<'a, T: Document>(updates: &mut UpdateTracker<'a>, index: &'a Index<'a>, documents: &[T], primary_key: &str) -> Result<()> {
+    for batch in documents.chunks(INDEX_BATCH_SIZE) {
+        updates.track(&index.uid, index.add_or_replace(batch, Some(primary_key)).await?);
+    }
+    Ok(())
+}
+
+/// remove blocks with their extrinsics, events and transfers
+async fn remove_blocks(client: &Client<'_>, numbers: &[u64]) -> Result<()> {
+    if numbers.is_empty() {
+        return Ok(());
+    }
+    let blocks_index = client.get_index(BLOCKS_INDEX).await?;
+    let extrinsics_index = client.get_index(EXTRINSICS_INDEX).await?;
+    let events_index = client.get_index(EVENTS_INDEX).await?;
+    let transfers_index = client.get_index(TRANSFERS_INDEX).await?;
+
+    let mut extrinsic_ids = vec![];
+    let mut event_ids = vec![];
+    for number in numbers {
+        let block = match blocks_index.get_document::<BlockDocument>(*number).await {
+            Ok(block) => block,
+            Err(_) => continue,
+        };
+        extrinsic_ids.extend((0..block.extrinsics_count).map(|index| document_id(*number, index)));
+        event_ids.extend((0..block.events_count).map(|index| document_id(*number, index)));
+    }
+
+    let mut updates = UpdateTracker::new();
+    for ids in extrinsic_ids.chunks(INDEX_BATCH_SIZE) {
+        updates.track(EXTRINSICS_INDEX, extrinsics_index.delete_documents(ids).await?);
+    }
+    for ids in event_ids.chunks(INDEX_BATCH_SIZE) {
+        updates.track(EVENTS_INDEX, events_index.delete_documents(ids).await?);
+        // transfers share the id of their event
+        updates.track(TRANSFERS_INDEX, transfers_index.delete_documents(ids).await?);
+    }
+    updates.track(BLOCKS_INDEX, blocks_index.delete_documents(numbers).await?);
+    updates.wait().await
+}
+
+/// move the activity of accounts last active in removed blocks back to their previous block,
+/// accounts without previous activity are deleted
+async fn rollback_accounts(client: &Client<'_>, redis_client: &RedisClient, accounts_key: &str, numbers: &[u64]) -> Result<()> {
+    if numbers.is_empty() {
+        return Ok(());
+    }
+    let accounts_index = client.get_index(ACCOUNTS_INDEX).await?;
+    let mut redis_con = redis_client.get_async_connection().await?;
+    let lock = lock_accounts(&mut redis_con, accounts_key).await?;
+    let mut updates = UpdateTracker::new();
+    let enqueued = restore_accounts(&mut updates, &accounts_index, &mut redis_con, accounts_key, numbers).await;
+    if !lock.release(&mut redis_con).await? {
+        llog::warn!("accounts lock {} expired while rolling back accounts", lock_key(accounts_key));
+    }
+    enqueued?;
+    updates.wait().await
+}
+
+async fn restore_accounts<'a>(updates: &mut UpdateTracker<'a>, index: &'a Index<'a>, con: &mut Connection, accounts_key: &str,
+                              numbers: &[u64]) -> Result<()> {
+    let (restored, inactive) = unmerge_accounts(con, accounts_key, numbers).await?;
+    add_or_replace(updates, index, &restored, "id").await?;
+    for ids in inactive.chunks(INDEX_BATCH_SIZE) {
+        updates.track(ACCOUNTS_INDEX, index.delete_documents(ids).await?);
+    }
+    Ok(())
+}
+
+/// restore accounts stored before `numbers` were written, returns the restored accounts and the ids of accounts
+/// without activity left
+async fn unmerge_accounts(con: &mut Connection, accounts_key: &str, numbers: &[u64]) -> Result<(Vec<AccountDocument>, Vec<String>)> {
+    let mut numbers = numbers.to_vec();
+    numbers.sort_by(|a, b| b.cmp(a));
+    let script = Script::new(ROLLBACK_ACCOUNTS_SCRIPT);
+    let mut invocation = script.prepare_invoke();
+    invocation.key(accounts_key);
+    for number in numbers.iter() {
+        invocation.arg(*number);
+    }
+    let restored: HashMap<String, i64> = invocation.invoke_async(con).await?;
+    let mut accounts = vec![];
+    let mut inactive = vec![];
+    for (id, last_active_block) in restored {
+        if last_active_block < 0 {
+            inactive.push(id);
+        } else {
+            accounts.push(AccountDocument { id, last_active_block: last_active_block as u64 });
+        }
+    }
+    Ok((accounts, inactive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+    use crate::collections::tests::test_connection;
+
+    fn account(id: &str, last_active_block: u64) -> AccountDocument {
+        AccountDocument { id: id.to_string(), last_active_block }
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_stored_activity() {
+        let mut con = match test_connection().await {
+            Some(con) => con,
+            None => return,
+        };
+        let key = "explorer:test:accounts";
+        let _: () = con.del(key).await.unwrap();
+
+        let mut accounts = vec![account("0x01", 7), account("0x02", 3)];
+        merge_accounts(&mut con, key, &[], &mut accounts).await.unwrap();
+        assert_eq!(accounts.iter().map(|a| a.last_active_block).collect::<Vec<_>>(), vec![7, 3]);
+
+        let mut accounts = vec![account("0x01", 5), account("0x02", 9)];
+        merge_accounts(&mut con, key, &[], &mut accounts).await.unwrap();
+        assert_eq!(accounts.iter().map(|a| a.last_active_block).collect::<Vec<_>>(), vec![7, 9]);
+        let _: () = con.del(key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_activity_before_removed_blocks() {
+        let mut con = match test_connection().await {
+            Some(con) => con,
+            None => return,
+        };
+        let key = "explorer:test:accounts:rollback";
+        let undo: Vec<String> = (10..=12).map(|number| format!("{}:undo:{}", key, number)).collect();
+        let _: () = con.del(key).await.unwrap();
+        let _: () = con.del(&undo).await.unwrap();
+
+        merge_accounts(&mut con, key, &[], &mut [account("0x01", 7)]).await.unwrap();
+        merge_accounts(&mut con, key, &[10], &mut [account("0x01", 10), account("0x02", 10)]).await.unwrap();
+        merge_accounts(&mut con, key, &[11], &mut [account("0x01", 11)]).await.unwrap();
+        merge_accounts(&mut con, key, &[12], &mut [account("0x03", 12)]).await.unwrap();
+
+        let (mut restored, inactive) = unmerge_accounts(&mut con, key, &[10, 11]).await.unwrap();
+        restored.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(restored.iter().map(|a| (a.id.as_str(), a.last_active_block)).collect::<Vec<_>>(), vec![("0x01", 7)]);
+        assert_eq!(inactive, vec!["0x02".to_string()]);
+        let stored: HashMap<String, u64> = con.hgetall(key).await.unwrap();
+        assert_eq!(stored.get("0x01"), Some(&7));
+        assert_eq!(stored.get("0x02"), None);
+        assert_eq!(stored.get("0x03"), Some(&12));
+        let _: () = con.del(key).await.unwrap();
+        let _: () = con.del(&undo).await.unwrap();
+    }
+}
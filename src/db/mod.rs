@@ -0,0 +1,95 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use meilisearch_sdk::client::Client;
+use redis::Client as RedisClient;
+use crate::config::{Settings, SinkKind};
+use crate::decoder::DecodedBlock;
+
+pub mod documents;
+pub mod settings;
+pub mod update;
+pub mod meilisearch;
+pub mod file;
+pub mod sqlite;
+
+use self::meilisearch::MeiliSink;
+use file::FileSink;
+use sqlite::SqliteSink;
+
+pub const BLOCKS_INDEX: &'static str = "blocks";
+pub const EXTRINSICS_INDEX: &'static str = "extrinsics";
+pub const EVENTS_INDEX: &'static str = "events";
+pub const ACCOUNTS_INDEX: &'static str = "accounts";
+pub const TRANSFERS_INDEX: &'static str = "transfers";
+
+/// indexes and their primary keys
+pub const INDEXES: &'static [(&'static str, &'static str)] = &[
+    (BLOCKS_INDEX, "number"),
+    (EXTRINSICS_INDEX, "id"),
+    (EVENTS_INDEX, "id"),
+    (ACCOUNTS_INDEX, "id"),
+    (TRANSFERS_INDEX, "id"),
+];
+
+/// Sink stores decoded blocks with their extrinsics and events
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// sink name
+    fn name(&self) -> &'static str;
+
+    /// prepare storage, called once on startup
+    async fn init(&self) -> Result<()>;
+
+    /// write decoded blocks, returns once they are stored so the checkpoint can advance
+    async fn write(&self, blocks: &[DecodedBlock]) -> Result<()>;
+
+    /// remove blocks, e.g. blocks of a stale fork
+    async fn remove(&self, numbers: &[u64]) -> Result<()>;
+
+    /// whether block `number` is stored with `hash`, confirms a duplicate filter hit before the block is skipped
+    async fn contains(&self, number: u64, hash: &str) -> Result<bool>;
+}
+
+/// sink selected by the `[sink]` section
+pub fn sink<'a>(settings: &Settings, meili_client: &'a Client<'a>, redis_client: &RedisClient) -> Result<Box<dyn Sink + 'a>> {
+    Ok(match settings.sink.kind {
+        SinkKind::Meilisearch => Box::new(MeiliSink::new(meili_client, redis_client, settings)),
+        SinkKind::File => Box::new(FileSink::new(&settings.sink.path)),
+        SinkKind::Sqlite => Box::new(SqliteSink::open(&settings.sink.path)?),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::json;
+    use crate::decoder::{DecodedBlock, DecodedExtrinsic, DecodedEvent};
+
+    /// block with one signed extrinsic and a transfer from its signer
+    pub(crate) fn block(number: u64, signer: &str) -> DecodedBlock {
+        DecodedBlock {
+            number,
+            hash: format!("0x{:064x}", number),
+            parent_hash: format!("0x{:064x}", number.saturating_sub(1)),
+            finalized: true,
+            spec_version: None,
+            extrinsics: vec![DecodedExtrinsic {
+                index: 0,
+                hash: "0x00".to_string(),
+                signed: true,
+                module: None,
+                call: None,
+                signer: Some(signer.to_string()),
+                args: None,
+                data: "0x".to_string(),
+            }],
+            events: vec![DecodedEvent {
+                index: 0,
+                extrinsic_index: Some(0),
+                module: "Balances".to_string(),
+                variant: "Transfer".to_string(),
+                args: Some(json!([signer, "0x02", "100"])),
+                data: "0x".to_string(),
+            }],
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::settings::Settings as MeiliSettings;
+use std::collections::HashMap;
+use crate::config::IndexSettings;
+use super::{BLOCKS_INDEX, EXTRINSICS_INDEX, EVENTS_INDEX, ACCOUNTS_INDEX, TRANSFERS_INDEX, INDEXES};
+use super::update::UpdateTracker;
+
+const DEFAULT_RANKING_RULES: &'static [&'static str] = &[
+    "typo", "words", "proximity", "attribute", "wordsPosition", "exactness",
+];
+
+/// default settings of an index, `[meilisearch.indexes.<uid>]` overrides them field by field
+pub fn default_settings(uid: &str) -> IndexSettings {
+    let (searchable, filterable): (&[&str], &[&str]) = match uid {
+        BLOCKS_INDEX => (
+            &["number", "hash", "parent_hash"],
+            &["finalized", "spec_version"],
+        ),
+        EXTRINSICS_INDEX => (
+            &["hash", "signer", "module", "call", "block_hash"],
+            &["module", "call", "signer", "success", "signed", "finalized", "block_number"],
+        ),
+        EVENTS_INDEX => (
+            &["module", "variant", "block_hash"],
+            &["module", "variant", "extrinsic_index", "finalized", "block_number"],
+        ),
+        ACCOUNTS_INDEX => (
+            &["id"],
+            &[],
+        ),
+        TRANSFERS_INDEX => (
+            &["from", "to"],
+            &["from", "to", "finalized", "block_number"],
+        ),
+        _ => (&["*"], &[]),
+    };
+    IndexSettings {
+        searchable_attributes: Some(strings(searchable)),
+        filterable_attributes: Some(strings(filterable)),
+        sortable_attributes: None,
+        ranking_rules: None,
+        synonyms: Some(HashMap::new()),
+        stop_words: Some(vec![]),
+    }
+}
+
+/// default settings overridden by `overrides`
+pub fn merge(defaults: IndexSettings, overrides: Option<&IndexSettings>) -> IndexSettings {
+    let overrides = match overrides {
+        Some(overrides) => overrides.clone(),
+        None => return defaults,
+    };
+    IndexSettings {
+        searchable_attributes: overrides.searchable_attributes.or(defaults.searchable_attributes),
+        filterable_attributes: overrides.filterable_attributes.or(defaults.filterable_attributes),
+        sortable_attributes: overrides.sortable_attributes.or(defaults.sortable_attributes),
+        ranking_rules: overrides.ranking_rules.or(defaults.ranking_rules),
+        synonyms: overrides.synonyms.or(defaults.synonyms),
+        stop_words: overrides.stop_words.or(defaults.stop_words),
+    }
+}
+
+/// meilisearch settings of an index. the pinned meilisearch filters on faceted attributes and
+/// predates sortable attributes, which are left out until the sdk supports them
+pub fn to_meili_settings(settings: &IndexSettings) -> MeiliSettings {
+    let ranking_rules = settings.ranking_rules.clone()
+        .unwrap_or_else(|| strings(DEFAULT_RANKING_RULES));
+    MeiliSettings {
+        synonyms: settings.synonyms.clone(),
+        stop_words: settings.stop_words.clone(),
+        ranking_rules: Some(ranking_rules),
+        attributes_for_faceting: settings.filterable_attributes.clone(),
+        distinct_attribute: None,
+        searchable_attributes: settings.searchable_attributes.clone(),
+        displayed_attributes: None,
+    }
+}
+
+/// settings in `desired` which differ from `live`, `None` if nothing changed.
+/// unchanged settings are left out so meilisearch does not re-index for them
+pub fn diff(desired: &MeiliSettings, live: &MeiliSettings) -> Option<MeiliSettings> {
+    let mut changes = MeiliSettings {
+        synonyms: None,
+        stop_words: None,
+        ranking_rules: None,
+        attributes_for_faceting: None,
+        distinct_attribute: None,
+        searchable_attributes: None,
+        displayed_attributes: None,
+    };
+    let mut changed = false;
+
+    if desired.synonyms.is_some() && desired.synonyms != live.synonyms {
+        changes.synonyms = desired.synonyms.clone();
+        changed = true;
+    }
+    if desired.stop_words.is_some() && sorted(&desired.stop_words) != sorted(&live.stop_words) {
+        changes.stop_words = desired.stop_words.clone();
+        changed = true;
+    }
+    if desired.ranking_rules.is_some() && desired.ranking_rules != live.ranking_rules {
+        changes.ranking_rules = desired.ranking_rules.clone();
+        changed = true;
+    }
+    if desired.attributes_for_faceting.is_some()
+        && sorted(&desired.attributes_for_faceting) != sorted(&live.attributes_for_faceting) {
+        changes.attributes_for_faceting = desired.attributes_for_faceting.clone();
+        changed = true;
+    }
+    // order of searchable attributes is their ranking order
+    if desired.searchable_attributes.is_some() && desired.searchable_attributes != live.searchable_attributes {
+        changes.searchable_attributes = desired.searchable_attributes.clone();
+        changed = true;
+    }
+
+    if changed { Some(changes) } else { None }
+}
+
+/// apply settings of all indexes, only changed settings are sent
+pub async fn apply(client: &Client<'_>, overrides: &HashMap<String, IndexSettings>) -> Result<()> {
+    for (uid, _) in INDEXES.iter() {
+        let settings = merge(default_settings(uid), overrides.get(*uid));
+        if settings.sortable_attributes.iter().flatten().next().is_some() {
+            llog::warn!("meilisearch index {} sortable_attributes are not supported by the pinned meilisearch, ignored", uid);
+        }
+        let index = client.get_index(uid).await?;
+        let live = index.get_settings().await?;
+        if let Some(changes) = diff(&to_meili_settings(&settings), &live) {
+            let mut updates = UpdateTracker::new();
+            updates.track(uid, index.set_settings(&changes).await?);
+            updates.wait().await?;
+            llog::info!("meilisearch index {} settings updated", uid);
+        }
+    }
+    Ok(())
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn sorted(values: &Option<Vec<String>>) -> Vec<String> {
+    let mut values = values.clone().unwrap_or_default();
+    values.sort();
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sortable_attributes_are_not_ranking_rules() {
+        let settings = IndexSettings {
+            sortable_attributes: Some(strings(&["block_number"])),
+            ..default_settings(EXTRINSICS_INDEX)
+        };
+        let meili = to_meili_settings(&settings);
+        assert_eq!(meili.ranking_rules, Some(strings(DEFAULT_RANKING_RULES)));
+    }
+
+    #[test]
+    fn overrides_replace_defaults_field_by_field() {
+        let overrides = IndexSettings {
+            stop_words: Some(strings(&["the"])),
+            ..IndexSettings::default()
+        };
+        let settings = merge(default_settings(EXTRINSICS_INDEX), Some(&overrides));
+        assert_eq!(settings.stop_words, Some(strings(&["the"])));
+        assert_eq!(settings.filterable_attributes, default_settings(EXTRINSICS_INDEX).filterable_attributes);
+    }
+
+    #[test]
+    fn diff_leaves_out_unchanged_settings() {
+        let desired = to_meili_settings(&default_settings(BLOCKS_INDEX));
+        assert!(diff(&desired, &desired).is_none());
+
+        let mut live = to_meili_settings(&default_settings(BLOCKS_INDEX));
+        live.attributes_for_faceting = Some(strings(&["spec_version", "finalized"]));
+        assert!(diff(&desired, &live).is_none(), "faceting order is not a change");
+
+        live.stop_words = Some(strings(&["the"]));
+        let changes = diff(&desired, &live).unwrap();
+        assert_eq!(changes.stop_words, Some(vec![]));
+        assert!(changes.ranking_rules.is_none());
+        assert!(changes.searchable_attributes.is_none());
+    }
+}
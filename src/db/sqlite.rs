@@ -0,0 +1,228 @@
+use anyhow::{Result, Error};
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::decoder::DecodedBlock;
+use super::Sink;
+use super::documents::Documents;
+
+const SCHEMA: &'static str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    finalized INTEGER NOT NULL,
+    spec_version INTEGER,
+    timestamp INTEGER,
+    extrinsics_count INTEGER NOT NULL,
+    events_count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS extrinsics (
+    block_number INTEGER NOT NULL,
+    idx INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    signed INTEGER NOT NULL,
+    module TEXT,
+    call TEXT,
+    signer TEXT,
+    args TEXT,
+    success INTEGER,
+    finalized INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (block_number, idx)
+);
+CREATE TABLE IF NOT EXISTS events (
+    block_number INTEGER NOT NULL,
+    idx INTEGER NOT NULL,
+    extrinsic_index INTEGER,
+    module TEXT NOT NULL,
+    variant TEXT NOT NULL,
+    args TEXT,
+    finalized INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (block_number, idx)
+);
+CREATE TABLE IF NOT EXISTS accounts (
+    id TEXT PRIMARY KEY,
+    last_active_block INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS transfers (
+    id TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER,
+    \"from\" TEXT NOT NULL,
+    \"to\" TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    finalized INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS transfers_block_number ON transfers (block_number);
+";
+
+/// SqliteSink stores blocks, extrinsics, events, accounts and transfers in an embedded sqlite database
+pub struct SqliteSink {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteSink {
+    pub fn open(path: &str) -> Result<SqliteSink> {
+        if let Some(dir) = Path::new(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(SqliteSink {
+            connection: Arc::new(Mutex::new(Connection::open(path)?)),
+        })
+    }
+
+    /// run `f` with the connection off the async runtime
+    async fn blocking<F, R>(&self, f: F) -> Result<R>
+        where F: FnOnce(&mut Connection) -> Result<R> + Send + 'static, R: Send + 'static {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| Error::msg("sqlite connection lock poisoned"))?;
+            f(&mut connection)
+        }).await.map_err(|e| Error::msg(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.blocking(|connection| {
+            connection.execute_batch(SCHEMA)?;
+            Ok(())
+        }).await
+    }
+
+    async fn write(&self, blocks: &[DecodedBlock]) -> Result<()> {
+        let documents = Documents::from_blocks(blocks);
+        self.blocking(move |connection| {
+            let tx = connection.transaction()?;
+            for block in documents.blocks.iter() {
+                tx.execute(
+                    "INSERT OR REPLACE INTO blocks VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![block.number as i64, block.hash, block.parent_hash, block.finalized,
+                            block.spec_version, block.timestamp.map(|t| t as i64),
+                            block.extrinsics_count, block.events_count],
+                )?;
+            }
+            for extrinsic in documents.extrinsics.iter() {
+                tx.execute(
+                    "INSERT OR REPLACE INTO extrinsics VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![extrinsic.block_number as i64, extrinsic.index, extrinsic.hash, extrinsic.signed,
+                            extrinsic.module, extrinsic.call, extrinsic.signer,
+                            extrinsic.args.as_ref().map(|args| args.to_string()),
+                            extrinsic.success, extrinsic.finalized, extrinsic.data],
+                )?;
+            }
+            for event in documents.events.iter() {
+                tx.execute(
+                    "INSERT OR REPLACE INTO events VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![event.block_number as i64, event.index, event.extrinsic_index, event.module,
+                            event.variant, event.args.as_ref().map(|args| args.to_string()),
+                            event.finalized, event.data],
+                )?;
+            }
+            for transfer in documents.transfers.iter() {
+                tx.execute(
+                    "INSERT OR REPLACE INTO transfers VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![transfer.id, transfer.block_number as i64, transfer.extrinsic_index, transfer.from,
+                            transfer.to, transfer.amount, transfer.finalized],
+                )?;
+            }
+            // blocks are not written in order, keep the latest activity
+            for account in documents.accounts.iter() {
+                tx.execute(
+                    "INSERT INTO accounts VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE \
+                     SET last_active_block = MAX(last_active_block, excluded.last_active_block)",
+                    params![account.id, account.last_active_block as i64],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        }).await
+    }
+
+    async fn remove(&self, numbers: &[u64]) -> Result<()> {
+        let numbers = numbers.to_vec();
+        self.blocking(move |connection| {
+            let tx = connection.transaction()?;
+            for number in numbers {
+                tx.execute("DELETE FROM extrinsics WHERE block_number = ?1", params![number as i64])?;
+                tx.execute("DELETE FROM events WHERE block_number = ?1", params![number as i64])?;
+                tx.execute("DELETE FROM transfers WHERE block_number = ?1", params![number as i64])?;
+                tx.execute("DELETE FROM blocks WHERE number = ?1", params![number as i64])?;
+                // accounts last active in a removed block fall back to their latest remaining activity
+                tx.execute(
+                    "UPDATE accounts SET last_active_block = MAX( \
+                         COALESCE((SELECT MAX(block_number) FROM extrinsics WHERE signer = accounts.id), -1), \
+                         COALESCE((SELECT MAX(block_number) FROM transfers \
+                                   WHERE \"from\" = accounts.id OR \"to\" = accounts.id), -1)) \
+                     WHERE last_active_block = ?1",
+                    params![number as i64],
+                )?;
+            }
+            tx.execute("DELETE FROM accounts WHERE last_active_block < 0", params![])?;
+            tx.commit()?;
+            Ok(())
+        }).await
+    }
+
+    async fn contains(&self, number: u64, hash: &str) -> Result<bool> {
+        let hash = hash.to_string();
+        self.blocking(move |connection| {
+            let stored: i64 = connection.query_row(
+                "SELECT COUNT(*) FROM blocks WHERE number = ?1 AND hash = ?2",
+                params![number as i64, hash],
+                |row| row.get(0),
+            )?;
+            Ok(stored > 0)
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::block;
+
+    fn count(sink: &SqliteSink, table: &str) -> i64 {
+        let connection = sink.connection.lock().unwrap();
+        connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get(0)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_all_documents() {
+        let path = std::env::temp_dir().join(format!("explorer-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = SqliteSink::open(path.to_str().unwrap()).unwrap();
+        sink.init().await.unwrap();
+
+        sink.write(&[block(7, "0x01")]).await.unwrap();
+        sink.write(&[block(3, "0x01")]).await.unwrap();
+        for (table, rows) in [("blocks", 2), ("extrinsics", 2), ("events", 2), ("transfers", 2), ("accounts", 2)].iter() {
+            assert_eq!(count(&sink, table), *rows, "{} rows", table);
+        }
+        let last_active: i64 = sink.connection.lock().unwrap()
+            .query_row("SELECT last_active_block FROM accounts WHERE id = '0x01'", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(last_active, 7);
+
+        assert!(sink.contains(3, &block(3, "0x01").hash).await.unwrap());
+        assert!(!sink.contains(3, &block(4, "0x01").hash).await.unwrap());
+
+        sink.remove(&[3]).await.unwrap();
+        assert!(!sink.contains(3, &block(3, "0x01").hash).await.unwrap());
+        assert_eq!(count(&sink, "blocks"), 1);
+        assert_eq!(count(&sink, "transfers"), 1);
+        assert_eq!(count(&sink, "accounts"), 2);
+
+        sink.remove(&[7]).await.unwrap();
+        assert_eq!(count(&sink, "accounts"), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
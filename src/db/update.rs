@@ -0,0 +1,51 @@
+use anyhow::{Result, Error};
+use meilisearch_sdk::progress::{Progress, UpdateStatus};
+use tokio::time::{sleep, Instant};
+use crate::config::{UPDATE_POLL_INTERVAL, UPDATE_TIMEOUT};
+
+/// UpdateTracker tracks asynchronous meilisearch updates of a write,
+/// `wait` polls them until processed and fails if any update failed
+pub struct UpdateTracker<'a> {
+    pending: Vec<(String, Progress<'a>)>,
+}
+
+impl<'a> UpdateTracker<'a> {
+    pub fn new() -> UpdateTracker<'a> {
+        UpdateTracker {
+            pending: vec![],
+        }
+    }
+
+    pub fn track(&mut self, uid: &str, progress: Progress<'a>) {
+        self.pending.push((uid.to_string(), progress));
+    }
+
+    pub async fn wait(self) -> Result<()> {
+        let deadline = Instant::now() + UPDATE_TIMEOUT;
+        let mut failures = vec![];
+
+        for (uid, progress) in self.pending.iter() {
+            loop {
+                match progress.get_status().await? {
+                    UpdateStatus::Processed { .. } => break,
+                    UpdateStatus::Failed { content } => {
+                        failures.push(format!("{}: {:?}", uid, content));
+                        break;
+                    }
+                    _ if Instant::now() >= deadline => {
+                        return Err(Error::msg(format!("meilisearch update of index {} timed out", uid)));
+                    }
+                    _ => sleep(UPDATE_POLL_INTERVAL).await,
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+        for failure in failures.iter() {
+            llog::error!("meilisearch update failed, {}", failure);
+        }
+        Err(Error::msg(format!("{} meilisearch updates failed", failures.len())))
+    }
+}
@@ -0,0 +1,875 @@
+use anyhow::{Result, Error};
+use codec::{Decode, Encode, Compact};
+use frame_metadata::{DecodeDifferent, RuntimeMetadata as FrameMetadata, RuntimeMetadataPrefixed,
+                     StorageEntryModifier, StorageEntryType, StorageHasher};
+use serde_json::{json, Map, Value};
+use redis::Client as RedisClient;
+use substrate_subxt::Client;
+use substrate_subxt::sp_core::bytes::{from_hex, to_hex};
+use substrate_subxt::sp_core::hashing::{blake2_128, blake2_256, twox_64, twox_128, twox_256};
+use substrate_subxt::sp_core::storage::StorageKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::runtime::Runtime;
+use super::{DecodedBlock, DecodedExtrinsic, DecodedEvent, rpc, storage, events_key};
+use super::metadata::MetadataCache;
+
+/// max nesting of type definitions, guards against alias cycles
+const MAX_TYPE_DEPTH: usize = 64;
+
+/// type definitions shared by most substrate chains, `[chain.types]` overrides them
+const BUILTIN_TYPES: &'static [(&'static str, &'static str)] = &[
+    ("AccountId", "[u8;32]"),
+    ("AccountIdOf", "AccountId"),
+    ("AccountIndex", "u32"),
+    ("Address", "MultiAddress"),
+    ("LookupSource", "Address"),
+    ("Source", "LookupSource"),
+    ("Balance", "u128"),
+    ("BalanceOf", "Balance"),
+    ("BalanceStatus", "u8"),
+    ("Status", "BalanceStatus"),
+    ("BlockNumber", "u32"),
+    ("Hash", "[u8;32]"),
+    ("H256", "[u8;32]"),
+    ("H160", "[u8;20]"),
+    ("Index", "u32"),
+    ("Moment", "u64"),
+    ("Weight", "u64"),
+    ("DispatchClass", "u8"),
+    ("Pays", "u8"),
+    ("DispatchInfo", "(Weight,DispatchClass,Pays)"),
+    ("DispatchResult", "Result<(),DispatchError>"),
+    ("SessionIndex", "u32"),
+    ("EraIndex", "u32"),
+    ("AuthorityId", "[u8;32]"),
+    ("AuthorityWeight", "u64"),
+    ("AuthorityList", "Vec<(AuthorityId,AuthorityWeight)>"),
+    ("Perbill", "u32"),
+    ("Permill", "u32"),
+    ("Percent", "u8"),
+    ("Bytes", "Vec<u8>"),
+    ("Text", "String"),
+    ("Key", "Vec<u8>"),
+    ("KeyValue", "(Vec<u8>,Vec<u8>)"),
+    ("OpaqueCall", "Vec<u8>"),
+    ("CallHash", "[u8;32]"),
+    ("Timepoint", "(BlockNumber,u32)"),
+    ("ProxyType", "u8"),
+    ("MemberCount", "u32"),
+    ("ProposalIndex", "u32"),
+    ("ReferendumIndex", "u32"),
+    ("PropIndex", "u32"),
+    ("VoteThreshold", "u8"),
+    ("ParaId", "u32"),
+];
+
+/// CallMeta is a dispatchable call of a runtime module
+#[derive(Debug, Clone)]
+pub struct CallMeta {
+    pub module: String,
+    pub name: String,
+    pub args: Vec<(String, String)>,
+}
+
+/// EventMeta is an event of a runtime module
+#[derive(Debug, Clone)]
+pub struct EventMeta {
+    pub module: String,
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// StorageMeta is a storage item of a runtime module
+#[derive(Debug, Clone)]
+pub struct StorageMeta {
+    pub prefix: String,
+    pub name: String,
+    /// hasher and type of each map key, empty for plain values
+    pub keys: Vec<(StorageHasher, String)>,
+    pub value: String,
+    /// value of an absent item, `None` for optional items
+    pub default: Option<Vec<u8>>,
+}
+
+impl StorageMeta {
+    /// storage key of the item, `keys` are the SCALE encoded map keys
+    pub fn key(&self, keys: &[Vec<u8>]) -> Result<StorageKey> {
+        if keys.len() != self.keys.len() {
+            return Err(Error::msg(format!("storage {}.{} expects {} keys, got {}",
+                                          self.prefix, self.name, self.keys.len(), keys.len())));
+        }
+        let mut key = twox_128(self.prefix.as_bytes()).to_vec();
+        key.extend(twox_128(self.name.as_bytes()).iter());
+        for ((hasher, _), encoded) in self.keys.iter().zip(keys) {
+            key.extend(hash_key(hasher, encoded));
+        }
+        Ok(StorageKey(key))
+    }
+}
+
+fn hash_key(hasher: &StorageHasher, encoded: &[u8]) -> Vec<u8> {
+    match hasher {
+        StorageHasher::Blake2_128 => blake2_128(encoded).to_vec(),
+        StorageHasher::Blake2_256 => blake2_256(encoded).to_vec(),
+        StorageHasher::Blake2_128Concat => [&blake2_128(encoded)[..], encoded].concat(),
+        StorageHasher::Twox128 => twox_128(encoded).to_vec(),
+        StorageHasher::Twox256 => twox_256(encoded).to_vec(),
+        StorageHasher::Twox64Concat => [&twox_64(encoded)[..], encoded].concat(),
+        StorageHasher::Identity => encoded.to_vec(),
+    }
+}
+
+/// RuntimeMetadata is the part of runtime metadata needed to decode extrinsics, events and storage
+#[derive(Debug, Clone)]
+pub struct RuntimeMetadata {
+    pub spec_version: u32,
+    pub calls: HashMap<(u8, u8), CallMeta>,
+    pub events: HashMap<(u8, u8), EventMeta>,
+    /// storage items by module and item name
+    pub storage: HashMap<(String, String), StorageMeta>,
+    pub signed_extensions: Vec<String>,
+}
+
+impl RuntimeMetadata {
+    /// parse SCALE encoded `RuntimeMetadataPrefixed`, only V13 metadata is supported
+    pub fn from_bytes(spec_version: u32, bytes: &[u8]) -> Result<RuntimeMetadata> {
+        let prefixed = RuntimeMetadataPrefixed::decode(&mut &bytes[..])
+            .map_err(|_| Error::msg(format!("runtime spec {} metadata decode error", spec_version)))?;
+        let metadata = match prefixed.1 {
+            FrameMetadata::V13(metadata) => metadata,
+            _ => return Err(Error::msg(format!("runtime spec {} metadata version is not supported", spec_version))),
+        };
+
+        let mut calls = HashMap::new();
+        let mut events = HashMap::new();
+        let mut storage = HashMap::new();
+        for module in decoded(&metadata.modules)? {
+            let module_name = decoded(&module.name)?;
+            if let Some(module_storage) = &module.storage {
+                let module_storage = decoded(module_storage)?;
+                let prefix = decoded(&module_storage.prefix)?;
+                for entry in decoded(&module_storage.entries)?.iter() {
+                    let name = decoded(&entry.name)?.clone();
+                    let (keys, value) = match &entry.ty {
+                        StorageEntryType::Plain(value) => (vec![], decoded(value)?.clone()),
+                        StorageEntryType::Map { hasher, key, value, .. } => {
+                            (vec![(hasher.clone(), decoded(key)?.clone())], decoded(value)?.clone())
+                        }
+                        StorageEntryType::DoubleMap { hasher, key1, key2, value, key2_hasher } => {
+                            (vec![(hasher.clone(), decoded(key1)?.clone()), (key2_hasher.clone(), decoded(key2)?.clone())],
+                             decoded(value)?.clone())
+                        }
+                        StorageEntryType::NMap { keys, hashers, value } => {
+                            (decoded(hashers)?.iter().cloned().zip(decoded(keys)?.iter().cloned()).collect(),
+                             decoded(value)?.clone())
+                        }
+                    };
+                    let default = match entry.modifier {
+                        StorageEntryModifier::Optional => None,
+                        StorageEntryModifier::Default => Some(decoded(&entry.default)?.clone()),
+                    };
+                    storage.insert((module_name.clone(), name.clone()), StorageMeta {
+                        prefix: prefix.clone(),
+                        name,
+                        keys,
+                        value,
+                        default,
+                    });
+                }
+            }
+            if let Some(module_calls) = &module.calls {
+                for (index, call) in decoded(module_calls)?.iter().enumerate() {
+                    let args = decoded(&call.arguments)?.iter()
+                        .map(|arg| Ok((decoded(&arg.name)?.clone(), decoded(&arg.ty)?.clone())))
+                        .collect::<Result<Vec<_>>>()?;
+                    calls.insert((module.index, index as u8), CallMeta {
+                        module: module_name.clone(),
+                        name: decoded(&call.name)?.clone(),
+                        args,
+                    });
+                }
+            }
+            if let Some(module_events) = &module.event {
+                for (index, event) in decoded(module_events)?.iter().enumerate() {
+                    events.insert((module.index, index as u8), EventMeta {
+                        module: module_name.clone(),
+                        name: decoded(&event.name)?.clone(),
+                        args: decoded(&event.arguments)?.clone(),
+                    });
+                }
+            }
+        }
+        let signed_extensions = metadata.extrinsic.signed_extensions.iter()
+            .map(|extension| decoded(extension).map(|name| name.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(RuntimeMetadata { spec_version, calls, events, storage, signed_extensions })
+    }
+}
+
+fn decoded<B, O>(value: &DecodeDifferent<B, O>) -> Result<&O>
+    where B: Encode + 'static, O: Encode + 'static {
+    match value {
+        DecodeDifferent::Decoded(value) => Ok(value),
+        DecodeDifferent::Encode(_) => Err(Error::msg("runtime metadata is not decoded")),
+    }
+}
+
+/// DynamicDecoder decodes blocks of any substrate chain from runtime metadata fetched over rpc,
+/// metadata is switched whenever the runtime spec version changes
+pub struct DynamicDecoder {
+    types: HashMap<String, String>,
+    metadata: MetadataCache,
+    /// metadata of the next block when decoding a range in order
+    next: Option<(u64, Arc<RuntimeMetadata>)>,
+}
+
+impl DynamicDecoder {
+    pub fn new(types: &HashMap<String, String>, redis_client: RedisClient, chain: &str) -> DynamicDecoder {
+        DynamicDecoder {
+            // config keys are case insensitive
+            types: types.iter().map(|(name, ty)| (name.to_lowercase(), ty.clone())).collect(),
+            metadata: MetadataCache::new(redis_client, chain),
+            next: None,
+        }
+    }
+
+    pub async fn decode_block(&mut self, client: &Client<Runtime>, number: u64, finalized: bool) -> Result<DecodedBlock> {
+        let hash: Option<String> = rpc(client, "chain_getBlockHash", &[json!(number)]).await?;
+        let hash = hash.ok_or_else(|| Error::msg(format!("block #{} hash not found", number)))?;
+        let block: Value = rpc(client, "chain_getBlock", &[json!(hash)]).await?;
+        let parent_hash = block["block"]["header"]["parentHash"].as_str()
+            .ok_or_else(|| Error::msg(format!("block #{} parent hash not found", number)))?
+            .to_string();
+
+        // a block is executed by the runtime of its parent state, which only changes
+        // after a block containing a runtime upgrade
+        let metadata = match self.next.take() {
+            Some((next, metadata)) if next == number => metadata,
+            _ => {
+                let state_hash = if number == 0 { &hash } else { &parent_hash };
+                self.metadata_at(client, state_hash).await?
+            }
+        };
+        let decoding = Decoding { metadata: &metadata, types: &self.types };
+
+        let extrinsics = block["block"]["extrinsics"].as_array()
+            .ok_or_else(|| Error::msg(format!("block #{} extrinsics not found", number)))?
+            .iter()
+            .enumerate()
+            .map(|(index, extrinsic)| {
+                let bytes = from_hex(extrinsic.as_str().unwrap_or_default())
+                    .map_err(|_| Error::msg("extrinsic hex decode error"))?;
+                decoding.extrinsic(index as u32, &bytes)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| Error::msg(format!("block #{} {}", number, e)))?;
+
+        let events = match storage(client, events_key(), &hash).await? {
+            Some(bytes) => decoding.events(&bytes)
+                .map_err(|e| Error::msg(format!("block #{} {}", number, e)))?,
+            None => vec![],
+        };
+
+        if events.iter().any(|event| event.module == "System" && event.variant == "CodeUpdated") {
+            llog::info!("runtime upgrade in block #{}, spec version {}", number, metadata.spec_version);
+        } else {
+            self.next = Some((number + 1, metadata.clone()));
+        }
+
+        Ok(DecodedBlock {
+            number,
+            hash,
+            parent_hash,
+            finalized,
+            spec_version: Some(metadata.spec_version),
+            extrinsics,
+            events,
+        })
+    }
+
+    /// decode storage item `module.name` at block `number`, or at the best block if `None`,
+    /// `keys` are the SCALE encoded map keys
+    pub async fn decode_storage(&mut self, client: &Client<Runtime>, number: Option<u64>, module: &str, name: &str,
+                                keys: &[Vec<u8>]) -> Result<Value> {
+        let params: Vec<Value> = number.map(|number| vec![json!(number)]).unwrap_or_default();
+        let hash: Option<String> = rpc(client, "chain_getBlockHash", &params).await?;
+        let hash = hash.ok_or_else(|| Error::msg(format!("block {:?} hash not found", number)))?;
+
+        let metadata = self.metadata_at(client, &hash).await?;
+        let meta = metadata.storage.get(&(module.to_string(), name.to_string()))
+            .ok_or_else(|| Error::msg(format!("storage {}.{} not found in runtime metadata", module, name)))?;
+        let encoded = storage(client, meta.key(keys)?, &hash).await?;
+        Decoding { metadata: &metadata, types: &self.types }.storage(meta, encoded.as_deref())
+    }
+
+    async fn metadata_at(&mut self, client: &Client<Runtime>, hash: &str) -> Result<Arc<RuntimeMetadata>> {
+        let version: Value = rpc(client, "state_getRuntimeVersion", &[json!(hash)]).await?;
+        let spec_version = version["specVersion"].as_u64()
+            .ok_or_else(|| Error::msg("runtime spec version not found"))? as u32;
+        self.metadata.get(client, spec_version, hash).await
+    }
+}
+
+/// Decoding decodes SCALE values by type name with one runtime metadata
+struct Decoding<'a> {
+    metadata: &'a RuntimeMetadata,
+    types: &'a HashMap<String, String>,
+}
+
+impl<'a> Decoding<'a> {
+    fn extrinsic(&self, index: u32, encoded: &[u8]) -> Result<DecodedExtrinsic> {
+        let mut input = encoded;
+        let _len: Compact<u32> = scale(&mut input, "extrinsic length")?;
+        let version: u8 = scale(&mut input, "extrinsic version")?;
+        if version & 0b0111_1111 != 4 {
+            return Err(Error::msg(format!("extrinsic version {} is not supported", version & 0b0111_1111)));
+        }
+
+        let signed = version & 0b1000_0000 != 0;
+        let mut signer = None;
+        if signed {
+            let address = self.decode("Address", &mut input, 0)?;
+            signer = Some(match address.get("Id") {
+                Some(Value::String(account)) => account.clone(),
+                _ => address.to_string(),
+            });
+            self.decode("MultiSignature", &mut input, 0)?;
+            for extension in self.metadata.signed_extensions.iter() {
+                let ty = self.types.get(&extension.to_lowercase())
+                    .map(|ty| ty.as_str())
+                    .unwrap_or_else(|| extension_type(extension));
+                self.decode(&normalize(ty), &mut input, 0)?;
+            }
+        }
+        let call_data = input;
+        let (call, args) = self.call_args(&mut input, 0)?;
+
+        Ok(DecodedExtrinsic {
+            index,
+            hash: to_hex(&blake2_256(encoded), false),
+            signed,
+            module: Some(call.module.clone()),
+            call: Some(call.name.clone()),
+            signer,
+            args: Some(Value::Object(args)),
+            data: to_hex(&call_data[..call_data.len() - input.len()], false),
+        })
+    }
+
+    fn events(&self, encoded: &[u8]) -> Result<Vec<DecodedEvent>> {
+        let mut input = encoded;
+        let count: Compact<u32> = scale(&mut input, "events length")?;
+        let mut events = Vec::with_capacity(count.0 as usize);
+        for index in 0..count.0 {
+            let phase: u8 = scale(&mut input, "event phase")?;
+            let extrinsic_index = match phase {
+                0 => Some(scale::<u32>(&mut input, "event phase")?),
+                _ => None,
+            };
+            let module: u8 = scale(&mut input, "event module")?;
+            let variant: u8 = scale(&mut input, "event variant")?;
+            let meta = self.metadata.events.get(&(module, variant))
+                .ok_or_else(|| Error::msg(format!("event {}:{} not found in runtime metadata", module, variant)))?;
+
+            let start = input;
+            let args = meta.args.iter()
+                .map(|ty| self.decode(&normalize(ty), &mut input, 0))
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| Error::msg(format!("event {}.{} {}", meta.module, meta.name, e)))?;
+            let data = to_hex(&start[..start.len() - input.len()], false);
+            let _topics = self.decode("Vec<Hash>", &mut input, 0)?;
+
+            events.push(DecodedEvent {
+                index,
+                extrinsic_index,
+                module: meta.module.clone(),
+                variant: meta.name.clone(),
+                args: Some(Value::Array(args)),
+                data,
+            });
+        }
+        Ok(events)
+    }
+
+    /// decode a storage value, an absent value falls back to the item default
+    fn storage<'b>(&self, meta: &'b StorageMeta, encoded: Option<&'b [u8]>) -> Result<Value> {
+        let mut input = match (encoded, &meta.default) {
+            (Some(encoded), _) => encoded,
+            (None, Some(default)) => &default[..],
+            (None, None) => return Ok(Value::Null),
+        };
+        self.decode(&normalize(&meta.value), &mut input, 0)
+            .map_err(|e| Error::msg(format!("storage {}.{} {}", meta.prefix, meta.name, e)))
+    }
+
+    fn call_args(&self, input: &mut &[u8], depth: usize) -> Result<(&'a CallMeta, Map<String, Value>)> {
+        let module: u8 = scale(input, "call module")?;
+        let call: u8 = scale(input, "call index")?;
+        let meta = self.metadata.calls.get(&(module, call))
+            .ok_or_else(|| Error::msg(format!("call {}:{} not found in runtime metadata", module, call)))?;
+        let mut args = Map::new();
+        for (name, ty) in meta.args.iter() {
+            let value = self.decode(&normalize(ty), input, depth + 1)
+                .map_err(|e| Error::msg(format!("call {}.{} {}", meta.module, meta.name, e)))?;
+            args.insert(name.clone(), value);
+        }
+        Ok((meta, args))
+    }
+
+    fn alias(&self, ty: &str) -> Option<&str> {
+        self.types.get(&ty.to_lowercase())
+            .map(|ty| ty.as_str())
+            .or_else(|| BUILTIN_TYPES.iter().find(|(name, _)| *name == ty).map(|(_, ty)| *ty))
+    }
+
+    fn decode(&self, ty: &str, input: &mut &[u8], depth: usize) -> Result<Value> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(Error::msg(format!("type {} is nested too deep", ty)));
+        }
+        if let Some(alias) = self.alias(ty) {
+            return self.decode(&normalize(alias), input, depth + 1);
+        }
+
+        match ty {
+            "()" => return Ok(Value::Null),
+            "bool" => return Ok(json!(scale::<bool>(input, ty)?)),
+            "u8" => return Ok(json!(scale::<u8>(input, ty)?)),
+            "u16" => return Ok(json!(scale::<u16>(input, ty)?)),
+            "u32" => return Ok(json!(scale::<u32>(input, ty)?)),
+            "u64" => return Ok(json!(scale::<u64>(input, ty)?)),
+            "u128" => return Ok(json!(scale::<u128>(input, ty)?.to_string())),
+            "i8" => return Ok(json!(scale::<i8>(input, ty)?)),
+            "i16" => return Ok(json!(scale::<i16>(input, ty)?)),
+            "i32" => return Ok(json!(scale::<i32>(input, ty)?)),
+            "i64" => return Ok(json!(scale::<i64>(input, ty)?)),
+            "i128" => return Ok(json!(scale::<i128>(input, ty)?.to_string())),
+            "String" => return Ok(json!(scale::<String>(input, ty)?)),
+            "Call" => {
+                let (call, args) = self.call_args(input, depth)?;
+                return Ok(json!({"module": call.module, "call": call.name, "args": args}));
+            }
+            "MultiAddress" => return self.multi_address(input, depth),
+            "MultiSignature" => return self.multi_signature(input),
+            "Era" => return era(input),
+            "DispatchError" => return dispatch_error(input),
+            _ => {}
+        }
+
+        if ty.starts_with('(') && ty.ends_with(')') {
+            return split_types(&ty[1..ty.len() - 1]).iter()
+                .map(|ty| self.decode(ty, input, depth + 1))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array);
+        }
+        if ty.starts_with('[') && ty.ends_with(']') {
+            let (inner, len) = ty[1..ty.len() - 1].rsplit_once(';')
+                .ok_or_else(|| Error::msg(format!("invalid array type {}", ty)))?;
+            let len = len.parse::<usize>().map_err(|_| Error::msg(format!("invalid array type {}", ty)))?;
+            if inner == "u8" {
+                return Ok(json!(to_hex(take(input, len, ty)?, false)));
+            }
+            return (0..len)
+                .map(|_| self.decode(inner, input, depth + 1))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array);
+        }
+
+        match split_generic(ty) {
+            ("Compact", Some(_)) => {
+                let value: Compact<u128> = scale(input, ty)?;
+                Ok(if value.0 <= u64::MAX as u128 { json!(value.0 as u64) } else { json!(value.0.to_string()) })
+            }
+            ("Vec", Some("u8")) => Ok(json!(to_hex(&scale::<Vec<u8>>(input, ty)?, false))),
+            ("Vec", Some(inner)) => {
+                let len: Compact<u32> = scale(input, ty)?;
+                (0..len.0)
+                    .map(|_| self.decode(inner, input, depth + 1))
+                    .collect::<Result<Vec<_>>>()
+                    .map(Value::Array)
+            }
+            ("Option", Some(inner)) => match scale::<u8>(input, ty)? {
+                0 => Ok(Value::Null),
+                1 => self.decode(inner, input, depth + 1),
+                _ => Err(Error::msg(format!("invalid {} variant", ty))),
+            },
+            ("Result", Some(inner)) => {
+                let types = split_types(inner);
+                if types.len() != 2 {
+                    return Err(Error::msg(format!("invalid result type {}", ty)));
+                }
+                match scale::<u8>(input, ty)? {
+                    0 => Ok(json!({"Ok": self.decode(types[0], input, depth + 1)?})),
+                    1 => Ok(json!({"Err": self.decode(types[1], input, depth + 1)?})),
+                    _ => Err(Error::msg(format!("invalid {} variant", ty))),
+                }
+            }
+            ("Box", Some(inner)) => self.decode(inner, input, depth + 1),
+            // generic aliases such as `BalanceOf<T, I>` or `Timepoint<BlockNumber>`
+            (name, Some(_)) if self.alias(name).is_some() => self.decode(name, input, depth + 1),
+            _ => Err(Error::msg(format!("unknown type {}, define it in [chain.types]", ty))),
+        }
+    }
+
+    fn multi_address(&self, input: &mut &[u8], depth: usize) -> Result<Value> {
+        match scale::<u8>(input, "MultiAddress")? {
+            0 => Ok(json!({"Id": self.decode("AccountId", input, depth + 1)?})),
+            1 => Ok(json!({"Index": self.decode("Compact<AccountIndex>", input, depth + 1)?})),
+            2 => Ok(json!({"Raw": self.decode("Vec<u8>", input, depth + 1)?})),
+            3 => Ok(json!({"Address32": self.decode("[u8;32]", input, depth + 1)?})),
+            4 => Ok(json!({"Address20": self.decode("[u8;20]", input, depth + 1)?})),
+            variant => Err(Error::msg(format!("invalid MultiAddress variant {}", variant))),
+        }
+    }
+
+    fn multi_signature(&self, input: &mut &[u8]) -> Result<Value> {
+        match scale::<u8>(input, "MultiSignature")? {
+            0 => Ok(json!({"Ed25519": to_hex(take(input, 64, "MultiSignature")?, false)})),
+            1 => Ok(json!({"Sr25519": to_hex(take(input, 64, "MultiSignature")?, false)})),
+            2 => Ok(json!({"Ecdsa": to_hex(take(input, 65, "MultiSignature")?, false)})),
+            variant => Err(Error::msg(format!("invalid MultiSignature variant {}", variant))),
+        }
+    }
+}
+
+fn era(input: &mut &[u8]) -> Result<Value> {
+    match scale::<u8>(input, "Era")? {
+        0 => Ok(json!("Immortal")),
+        first => Ok(json!({"Mortal": [first, scale::<u8>(input, "Era")?]})),
+    }
+}
+
+fn dispatch_error(input: &mut &[u8]) -> Result<Value> {
+    match scale::<u8>(input, "DispatchError")? {
+        0 => Ok(json!("Other")),
+        1 => Ok(json!("CannotLookup")),
+        2 => Ok(json!("BadOrigin")),
+        3 => Ok(json!({"Module": {
+            "index": scale::<u8>(input, "DispatchError")?,
+            "error": scale::<u8>(input, "DispatchError")?,
+        }})),
+        4 => Ok(json!("ConsumerRemaining")),
+        5 => Ok(json!("NoProviders")),
+        6 => Ok(json!({"Token": scale::<u8>(input, "DispatchError")?})),
+        7 => Ok(json!({"Arithmetic": scale::<u8>(input, "DispatchError")?})),
+        variant => Err(Error::msg(format!("invalid DispatchError variant {}", variant))),
+    }
+}
+
+/// extra data type of the well known signed extensions
+fn extension_type(name: &str) -> &'static str {
+    match name {
+        "CheckMortality" | "CheckEra" => "Era",
+        "CheckNonce" => "Compact<Index>",
+        "ChargeTransactionPayment" => "Compact<Balance>",
+        _ => "()",
+    }
+}
+
+fn scale<T: Decode>(input: &mut &[u8], ty: &str) -> Result<T> {
+    T::decode(input).map_err(|e| Error::msg(format!("decode {} error: {}", ty, e)))
+}
+
+fn take<'b>(input: &mut &'b [u8], len: usize, ty: &str) -> Result<&'b [u8]> {
+    if input.len() < len {
+        return Err(Error::msg(format!("decode {} error: not enough data", ty)));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+/// strip whitespace and qualified paths, `Vec<<T as Config>::Call>` becomes `Vec<Call>`
+fn normalize(ty: &str) -> String {
+    let mut ty: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    while let Some(end) = ty.find(">::") {
+        let mut depth = 0;
+        let mut start = None;
+        for (i, c) in ty[..=end].char_indices().rev() {
+            match c {
+                '>' => depth += 1,
+                '<' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        start = Some(i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        match start {
+            Some(start) => ty.replace_range(start..end + 3, ""),
+            None => break,
+        }
+    }
+    ty.replace("T::", "")
+}
+
+/// split `Name<Inner>` into `("Name", Some("Inner"))`
+fn split_generic(ty: &str) -> (&str, Option<&str>) {
+    match ty.find('<') {
+        Some(i) if ty.ends_with('>') => (&ty[..i], Some(&ty[i + 1..ty.len() - 1])),
+        _ => (ty, None),
+    }
+}
+
+/// split comma separated types, ignoring commas of nested types
+fn split_types(types: &str) -> Vec<&str> {
+    let mut res = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in types.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(&types[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < types.len() {
+        res.push(&types[start..]);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::events_key;
+
+    fn metadata() -> RuntimeMetadata {
+        let mut calls = HashMap::new();
+        calls.insert((3, 0), CallMeta {
+            module: "Timestamp".to_string(),
+            name: "set".to_string(),
+            args: vec![("now".to_string(), "Compact<T::Moment>".to_string())],
+        });
+        calls.insert((5, 0), CallMeta {
+            module: "Balances".to_string(),
+            name: "transfer".to_string(),
+            args: vec![
+                ("dest".to_string(), "<T::Lookup as StaticLookup>::Source".to_string()),
+                ("value".to_string(), "Compact<T::Balance>".to_string()),
+            ],
+        });
+        let mut events = HashMap::new();
+        events.insert((5, 2), EventMeta {
+            module: "Balances".to_string(),
+            name: "Transfer".to_string(),
+            args: vec!["AccountId".to_string(), "AccountId".to_string(), "Balance".to_string()],
+        });
+        let mut storage = HashMap::new();
+        storage.insert(("System".to_string(), "Events".to_string()), StorageMeta {
+            prefix: "System".to_string(),
+            name: "Events".to_string(),
+            keys: vec![],
+            value: "Vec<EventRecord<T::Event, T::Hash>>".to_string(),
+            default: Some(vec![0]),
+        });
+        RuntimeMetadata { spec_version: 1, calls, events, storage, signed_extensions: vec![] }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        from_hex(s).unwrap()
+    }
+
+    fn decode(types: &HashMap<String, String>, ty: &str, encoded: &str) -> Result<Value> {
+        let metadata = metadata();
+        let decoding = Decoding { metadata: &metadata, types };
+        let bytes = hex(encoded);
+        let mut input = &bytes[..];
+        let value = decoding.decode(&normalize(ty), &mut input, 0)?;
+        assert!(input.is_empty(), "{} left {} bytes", ty, input.len());
+        Ok(value)
+    }
+
+    #[test]
+    fn normalize_strips_whitespace_and_paths() {
+        let cases = [
+            ("u8", "u8"),
+            ("T::AccountId", "AccountId"),
+            (" Compact< T::Balance > ", "Compact<Balance>"),
+            ("Vec<<T as Config>::Call>", "Vec<Call>"),
+            ("<T::Lookup as StaticLookup>::Source", "Source"),
+            ("<<T as Trait>::A as B>::C", "C"),
+            ("Vec<(T::AccountId, BalanceOf<T, I>)>", "Vec<(AccountId,BalanceOf<T,I>)>"),
+        ];
+        for (ty, expected) in cases.iter() {
+            assert_eq!(normalize(ty), *expected, "normalize {}", ty);
+        }
+    }
+
+    #[test]
+    fn split_types_ignores_nested_commas() {
+        let cases: [(&str, Vec<&str>); 5] = [
+            ("", vec![]),
+            ("u8", vec!["u8"]),
+            ("u8,u16", vec!["u8", "u16"]),
+            ("BalanceOf<T,I>,u32", vec!["BalanceOf<T,I>", "u32"]),
+            ("AccountId,Vec<(u8,u16)>,[u8;32]", vec!["AccountId", "Vec<(u8,u16)>", "[u8;32]"]),
+        ];
+        for (types, expected) in cases.iter() {
+            assert_eq!(&split_types(types), expected, "split_types {}", types);
+        }
+    }
+
+    #[test]
+    fn split_generic_splits_outer_type() {
+        let cases = [
+            ("u8", ("u8", None)),
+            ("Vec<u8>", ("Vec", Some("u8"))),
+            ("Option<Vec<u8>>", ("Option", Some("Vec<u8>"))),
+            ("Result<(),DispatchError>", ("Result", Some("(),DispatchError"))),
+            ("Vec<u8", ("Vec<u8", None)),
+        ];
+        for (ty, expected) in cases.iter() {
+            assert_eq!(split_generic(ty), *expected, "split_generic {}", ty);
+        }
+    }
+
+    #[test]
+    fn decode_known_encodings() {
+        let account = format!("0x{}", "01".repeat(32));
+        let cases = vec![
+            ("bool", "0x01".to_string(), json!(true)),
+            ("u8", "0x2a".to_string(), json!(42)),
+            ("u16", "0x0100".to_string(), json!(1)),
+            ("u32", "0x01000000".to_string(), json!(1)),
+            ("i8", "0xff".to_string(), json!(-1)),
+            ("u128", format!("0x01{}", "00".repeat(15)), json!("1")),
+            ("Balance", format!("0x64{}", "00".repeat(15)), json!("100")),
+            ("BalanceOf<T, I>", format!("0x64{}", "00".repeat(15)), json!("100")),
+            ("Compact<u32>", "0x04".to_string(), json!(1)),
+            ("Compact<u32>", "0x0101".to_string(), json!(64)),
+            ("Compact<T::Moment>", "0xa10f".to_string(), json!(1000)),
+            ("String", "0x0c616263".to_string(), json!("abc")),
+            ("Vec<u8>", "0x0c010203".to_string(), json!("0x010203")),
+            ("Vec<u16>", "0x0801000200".to_string(), json!([1, 2])),
+            ("[u8;4]", "0x01020304".to_string(), json!("0x01020304")),
+            ("[u16;2]", "0x01000200".to_string(), json!([1, 2])),
+            ("(u8,u16)", "0x010200".to_string(), json!([1, 2])),
+            ("()", "0x".to_string(), Value::Null),
+            ("Option<u32>", "0x00".to_string(), Value::Null),
+            ("Option<u32>", "0x0105000000".to_string(), json!(5)),
+            ("DispatchResult", "0x00".to_string(), json!({"Ok": null})),
+            ("DispatchResult", "0x01030502".to_string(), json!({"Err": {"Module": {"index": 5, "error": 2}}})),
+            ("Era", "0x00".to_string(), json!("Immortal")),
+            ("Era", "0x4500".to_string(), json!({"Mortal": [0x45, 0]})),
+            ("AccountId", account.clone(), json!(account)),
+            ("<T::Lookup as StaticLookup>::Source", format!("0x00{}", "01".repeat(32)), json!({"Id": account})),
+            ("Call", "0x0300a10f".to_string(), json!({"module": "Timestamp", "call": "set", "args": {"now": 1000}})),
+        ];
+        let types = HashMap::new();
+        for (ty, encoded, expected) in cases {
+            let value = decode(&types, ty, &encoded).unwrap_or_else(|e| panic!("decode {} {}: {}", ty, encoded, e));
+            assert_eq!(value, expected, "decode {} {}", ty, encoded);
+        }
+    }
+
+    #[test]
+    fn decode_configured_types() {
+        let mut types = HashMap::new();
+        types.insert("mytype".to_string(), "(u8,bool)".to_string());
+        types.insert("balance".to_string(), "u64".to_string());
+        assert_eq!(decode(&types, "MyType", "0x0201").unwrap(), json!([2, true]));
+        // `[chain.types]` overrides the builtin types
+        assert_eq!(decode(&types, "Balance", "0x0100000000000000").unwrap(), json!(1));
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut types = HashMap::new();
+        types.insert("a".to_string(), "B".to_string());
+        types.insert("b".to_string(), "A".to_string());
+        assert!(decode(&types, "A", "0x00").unwrap_err().to_string().contains("nested too deep"));
+        assert!(decode(&types, "Foo", "0x00").unwrap_err().to_string().contains("unknown type Foo"));
+        assert!(decode(&types, "u32", "0x01").is_err());
+        assert!(decode(&types, "Option<u8>", "0x02").is_err());
+        assert!(decode(&types, "Call", "0x0900").unwrap_err().to_string().contains("call 9:0 not found"));
+    }
+
+    #[test]
+    fn decode_unsigned_extrinsic() {
+        let metadata = metadata();
+        let types = HashMap::new();
+        let decoding = Decoding { metadata: &metadata, types: &types };
+        let encoded = hex("0x14040300a10f");
+        let extrinsic = decoding.extrinsic(0, &encoded).unwrap();
+        assert!(!extrinsic.signed);
+        assert_eq!(extrinsic.module.as_deref(), Some("Timestamp"));
+        assert_eq!(extrinsic.call.as_deref(), Some("set"));
+        assert_eq!(extrinsic.args, Some(json!({"now": 1000})));
+        assert_eq!(extrinsic.data, "0x0300a10f");
+        assert_eq!(extrinsic.hash, to_hex(&blake2_256(&encoded), false));
+    }
+
+    #[test]
+    fn decode_transfer_event() {
+        let metadata = metadata();
+        let types = HashMap::new();
+        let decoding = Decoding { metadata: &metadata, types: &types };
+        // one event in phase ApplyExtrinsic(1), Balances.Transfer(0x01.., 0x02.., 100), no topics
+        let encoded = hex(&format!("0x04 00 01000000 05 02 {} {} 64{} 00",
+                                   "01".repeat(32), "02".repeat(32), "00".repeat(15)).replace(' ', ""));
+        let events = decoding.events(&encoded).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].extrinsic_index, Some(1));
+        assert_eq!(events[0].module, "Balances");
+        assert_eq!(events[0].variant, "Transfer");
+        assert_eq!(events[0].args, Some(json!([
+            format!("0x{}", "01".repeat(32)),
+            format!("0x{}", "02".repeat(32)),
+            "100",
+        ])));
+    }
+
+    #[test]
+    fn storage_keys() {
+        let metadata = metadata();
+        let events = &metadata.storage[&("System".to_string(), "Events".to_string())];
+        assert_eq!(events.key(&[]).unwrap(), events_key());
+        assert!(events.key(&[vec![1]]).is_err());
+
+        let account = StorageMeta {
+            prefix: "System".to_string(),
+            name: "Account".to_string(),
+            keys: vec![(StorageHasher::Blake2_128Concat, "T::AccountId".to_string())],
+            value: "u32".to_string(),
+            default: None,
+        };
+        let id = vec![1u8; 32];
+        let key = account.key(&[id.clone()]).unwrap().0;
+        assert_eq!(&key[..16], &twox_128(b"System"));
+        assert_eq!(&key[16..32], &twox_128(b"Account"));
+        assert_eq!(&key[32..48], &blake2_128(&id));
+        assert_eq!(&key[48..], &id[..]);
+
+        assert_eq!(hash_key(&StorageHasher::Twox64Concat, &[7]), [&twox_64(&[7])[..], &[7u8][..]].concat());
+        assert_eq!(hash_key(&StorageHasher::Identity, &[7]), vec![7]);
+        assert_eq!(hash_key(&StorageHasher::Blake2_256, &[7]).len(), 32);
+    }
+
+    #[test]
+    fn storage_values_fall_back_to_default() {
+        let metadata = metadata();
+        let types = HashMap::new();
+        let decoding = Decoding { metadata: &metadata, types: &types };
+        let mut meta = StorageMeta {
+            prefix: "System".to_string(),
+            name: "Number".to_string(),
+            keys: vec![],
+            value: "T::BlockNumber".to_string(),
+            default: Some(vec![7, 0, 0, 0]),
+        };
+        assert_eq!(decoding.storage(&meta, Some(&[1u8, 0, 0, 0][..])).unwrap(), json!(1));
+        assert_eq!(decoding.storage(&meta, None).unwrap(), json!(7));
+        meta.default = None;
+        assert_eq!(decoding.storage(&meta, None).unwrap(), Value::Null);
+        assert!(decoding.storage(&meta, Some(&[1u8][..])).unwrap_err().to_string().contains("storage System.Number"));
+    }
+}
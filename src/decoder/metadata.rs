@@ -0,0 +1,63 @@
+use anyhow::{Result, Error};
+use cached::{Cached, SizedCache};
+use once_cell::sync::Lazy;
+use redis::{Client as RedisClient, AsyncCommands};
+use serde_json::json;
+use substrate_subxt::Client;
+use substrate_subxt::sp_core::Bytes;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::runtime::Runtime;
+use crate::config::{METADATA_KEY, METADATA_CACHE_SIZE};
+use super::rpc;
+use super::dynamic::RuntimeMetadata;
+
+/// metadata shared by the decoders of the process by chain and spec version, every task builds its own decoder
+static CACHE: Lazy<Mutex<SizedCache<(String, u32), Arc<RuntimeMetadata>>>> =
+    Lazy::new(|| Mutex::new(SizedCache::with_size(METADATA_CACHE_SIZE)));
+
+/// MetadataCache keeps runtime metadata by spec version in memory of the process,
+/// raw metadata is persisted by redis so restarted workers do not fetch it again
+pub struct MetadataCache {
+    redis_client: RedisClient,
+    chain: String,
+    key: String,
+}
+
+impl MetadataCache {
+    pub fn new(redis_client: RedisClient, chain: &str) -> MetadataCache {
+        MetadataCache {
+            redis_client,
+            chain: chain.to_string(),
+            key: format!("{}:{}", METADATA_KEY, chain),
+        }
+    }
+
+    /// metadata of `spec_version`, fetched at block `hash` on cache miss
+    pub async fn get(&mut self, client: &Client<Runtime>, spec_version: u32, hash: &str) -> Result<Arc<RuntimeMetadata>> {
+        let cache_key = (self.chain.clone(), spec_version);
+        let cached = lock_cache()?.cache_get(&cache_key).cloned();
+        if let Some(metadata) = cached {
+            return Ok(metadata);
+        }
+
+        let mut redis_con = self.redis_client.get_async_connection().await?;
+        let stored: Option<Vec<u8>> = redis_con.hget(&self.key, spec_version).await?;
+        let bytes = match stored {
+            Some(bytes) => bytes,
+            None => {
+                let bytes: Bytes = rpc(client, "state_getMetadata", &[json!(hash)]).await?;
+                redis_con.hset(&self.key, spec_version, bytes.0.as_slice()).await?;
+                llog::info!("runtime spec version {} metadata fetched", spec_version);
+                bytes.0
+            }
+        };
+
+        let metadata = Arc::new(RuntimeMetadata::from_bytes(spec_version, &bytes)?);
+        lock_cache()?.cache_set(cache_key, metadata.clone());
+        Ok(metadata)
+    }
+}
+
+fn lock_cache() -> Result<MutexGuard<'static, SizedCache<(String, u32), Arc<RuntimeMetadata>>>> {
+    CACHE.lock().map_err(|_| Error::msg("metadata cache lock poisoned"))
+}
@@ -0,0 +1,239 @@
+use anyhow::{Result, Error};
+use codec::{Encode, Decode, Compact};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use redis::Client as RedisClient;
+use substrate_subxt::{Client, RawEvent};
+use substrate_subxt::events::Raw;
+use substrate_subxt::system::{System, Phase};
+use substrate_subxt::sp_core::bytes::to_hex;
+use substrate_subxt::sp_core::hashing::{blake2_256, twox_128};
+use substrate_subxt::sp_core::storage::{StorageKey, StorageData};
+use crate::runtime::Runtime;
+use crate::config::{Chain, DecoderMode};
+
+pub mod dynamic;
+pub mod metadata;
+
+use dynamic::DynamicDecoder;
+
+pub type BlockHash = <Runtime as System>::Hash;
+
+/// DecodedBlock is a block with its extrinsics and events decoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedBlock {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub finalized: bool,
+    /// runtime spec version, only known by the dynamic decoder
+    pub spec_version: Option<u32>,
+    pub extrinsics: Vec<DecodedExtrinsic>,
+    pub events: Vec<DecodedEvent>,
+}
+
+/// DecodedExtrinsic is an extrinsic of a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedExtrinsic {
+    pub index: u32,
+    pub hash: String,
+    pub signed: bool,
+    pub module: Option<String>,
+    pub call: Option<String>,
+    pub signer: Option<String>,
+    pub args: Option<Value>,
+    pub data: String,
+}
+
+/// DecodedEvent is an event emitted by a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedEvent {
+    pub index: u32,
+    pub extrinsic_index: Option<u32>,
+    pub module: String,
+    pub variant: String,
+    pub args: Option<Value>,
+    pub data: String,
+}
+
+/// BlockDecoder decodes blocks with the compile-time runtime,
+/// or from runtime metadata when `[chain] decoder = "dynamic"`
+pub struct BlockDecoder {
+    pub client: Client<Runtime>,
+    dynamic: Option<DynamicDecoder>,
+}
+
+impl BlockDecoder {
+    pub fn new(client: Client<Runtime>, chain: &Chain, redis_client: RedisClient) -> BlockDecoder {
+        let dynamic = match chain.decoder {
+            DecoderMode::Static => None,
+            DecoderMode::Dynamic => Some(DynamicDecoder::new(&chain.types, redis_client, &chain.name)),
+        };
+        BlockDecoder { client, dynamic }
+    }
+
+    pub async fn decode_block(&mut self, number: u64, finalized: bool) -> Result<DecodedBlock> {
+        match self.dynamic.as_mut() {
+            Some(dynamic) => dynamic.decode_block(&self.client, number, finalized).await,
+            None => decode_block(&self.client, number, finalized).await,
+        }
+    }
+}
+
+/// fetch block by number and decode its extrinsics and events
+pub async fn decode_block(client: &Client<Runtime>, number: u64, finalized: bool) -> Result<DecodedBlock> {
+    let hash = client.block_hash(Some(number.into())).await?
+        .ok_or_else(|| Error::msg(format!("block #{} hash not found", number)))?;
+    let block = client.block(Some(hash)).await?
+        .ok_or_else(|| Error::msg(format!("block #{} not found", number)))?;
+
+    let extrinsics = block.block.extrinsics.iter()
+        .enumerate()
+        .map(|(index, extrinsic)| decode_extrinsic(index as u32, &extrinsic.encode()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let events = decode_events(client, hash).await?;
+
+    Ok(DecodedBlock {
+        number,
+        hash: format!("{:?}", hash),
+        parent_hash: format!("{:?}", block.block.header.parent_hash),
+        finalized,
+        spec_version: None,
+        extrinsics,
+        events,
+    })
+}
+
+fn decode_extrinsic(index: u32, encoded: &[u8]) -> Result<DecodedExtrinsic> {
+    let mut input = encoded;
+    let _len = <Compact<u32>>::decode(&mut input)
+        .map_err(|_| Error::msg("extrinsic length decode error"))?;
+    let version = input.first()
+        .ok_or_else(|| Error::msg("extrinsic is empty"))?;
+
+    let signed = version & 0b1000_0000 != 0;
+
+    Ok(DecodedExtrinsic {
+        index,
+        hash: to_hex(&blake2_256(encoded), false),
+        signed,
+        module: None,
+        call: None,
+        signer: if signed { signer(&input[1..]) } else { None },
+        args: None,
+        data: to_hex(input, false),
+    })
+}
+
+/// signer of a signed extrinsic from its `MultiAddress::Id` address, the address type of the compile-time runtimes
+fn signer(address: &[u8]) -> Option<String> {
+    match address.split_first() {
+        Some((0, account)) if account.len() >= 32 => Some(to_hex(&account[..32], false)),
+        _ => None,
+    }
+}
+
+/// args of `Balances.Transfer(from, to, amount)` in the format of the dynamic decoder,
+/// other events are only decoded by the dynamic decoder
+fn event_args(module: &str, variant: &str, data: &[u8]) -> Option<Value> {
+    if module != "Balances" || variant != "Transfer" {
+        return None;
+    }
+    let (from, to, amount) = <([u8; 32], [u8; 32], u128)>::decode(&mut &data[..]).ok()?;
+    Some(json!([to_hex(&from, false), to_hex(&to, false), amount.to_string()]))
+}
+
+async fn decode_events(client: &Client<Runtime>, hash: BlockHash) -> Result<Vec<DecodedEvent>> {
+    let storage = match storage(client, events_key(), &format!("{:?}", hash)).await? {
+        Some(storage) => storage,
+        None => return Ok(vec![]),
+    };
+
+    let events = client.events_decoder().decode_events(&mut &storage[..])?;
+    Ok(events.into_iter()
+        .enumerate()
+        .map(|(index, (phase, raw))| {
+            let extrinsic_index = match phase {
+                Phase::ApplyExtrinsic(i) => Some(i),
+                _ => None,
+            };
+            match raw {
+                Raw::Event(RawEvent { module, variant, data }) => DecodedEvent {
+                    index: index as u32,
+                    extrinsic_index,
+                    args: event_args(&module, &variant, &data),
+                    module,
+                    variant,
+                    data: to_hex(&data, false),
+                },
+                Raw::Error(err) => DecodedEvent {
+                    index: index as u32,
+                    extrinsic_index,
+                    module: "System".to_string(),
+                    variant: "ExtrinsicFailed".to_string(),
+                    args: None,
+                    data: err.to_string(),
+                },
+            }
+        })
+        .collect())
+}
+
+/// storage key of `System::Events`
+pub(crate) fn events_key() -> StorageKey {
+    let mut key = twox_128(b"System").to_vec();
+    key.extend(twox_128(b"Events").iter());
+    StorageKey(key)
+}
+
+/// raw storage value at block `hash`
+pub(crate) async fn storage(client: &Client<Runtime>, key: StorageKey, hash: &str) -> Result<Option<Vec<u8>>> {
+    let storage: Option<StorageData> = rpc(client, "state_getStorage", &[
+        serde_json::to_value(key)?,
+        Value::String(hash.to_string()),
+    ]).await?;
+    Ok(storage.map(|storage| storage.0))
+}
+
+pub(crate) async fn rpc<T: DeserializeOwned>(client: &Client<Runtime>, method: &str, params: &[Value]) -> Result<T> {
+    let res = client.rpc_client().request(method, params).await?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_extrinsic_signer() {
+        let account = [1u8; 32];
+        // length, signed version 4, MultiAddress::Id, rest of the extrinsic
+        let mut encoded = Compact(36u32).encode();
+        encoded.extend(&[0x84, 0]);
+        encoded.extend(&account);
+        encoded.extend(&[0, 0]);
+        let extrinsic = decode_extrinsic(0, &encoded).unwrap();
+        assert!(extrinsic.signed);
+        assert_eq!(extrinsic.signer, Some(to_hex(&account, false)));
+
+        let extrinsic = decode_extrinsic(0, &[Compact(3u32).encode(), vec![0x04, 3, 0]].concat()).unwrap();
+        assert!(!extrinsic.signed);
+        assert_eq!(extrinsic.signer, None);
+        // only `MultiAddress::Id` names an account
+        assert_eq!(signer(&[1, 4]), None);
+        assert_eq!(signer(&[0, 1]), None);
+    }
+
+    #[test]
+    fn static_transfer_args() {
+        let data = ([1u8; 32], [2u8; 32], 100u128).encode();
+        assert_eq!(event_args("Balances", "Transfer", &data), Some(json!([
+            to_hex(&[1u8; 32], false),
+            to_hex(&[2u8; 32], false),
+            "100",
+        ])));
+        assert_eq!(event_args("Balances", "Deposit", &data), None);
+        assert_eq!(event_args("Balances", "Transfer", &data[..40]), None);
+    }
+}
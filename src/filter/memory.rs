@@ -0,0 +1,67 @@
+use anyhow::{Result, Error};
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use redis::Client as RedisClient;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::config::Settings;
+use super::Filter;
+
+/// fingerprints shared by the memory filters of the process, every task builds its own filter
+static FINGERPRINTS: Lazy<Arc<Mutex<HashSet<Vec<u8>>>>> = Lazy::new(Default::default);
+
+/// MemoryFilter keeps exact fingerprints in a process local HashSet, e.g. for a single worker or CI
+#[derive(Debug, Default)]
+pub struct MemoryFilter {
+    fingerprints: Arc<Mutex<HashSet<Vec<u8>>>>,
+}
+
+impl MemoryFilter {
+    /// filter with its own fingerprints
+    pub fn new() -> MemoryFilter {
+        MemoryFilter::default()
+    }
+
+    /// filter on the fingerprints shared by the process
+    pub fn shared() -> MemoryFilter {
+        MemoryFilter {
+            fingerprints: FINGERPRINTS.clone(),
+        }
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.fingerprints()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.fingerprints()?.is_empty())
+    }
+
+    fn fingerprints(&self) -> Result<MutexGuard<HashSet<Vec<u8>>>> {
+        self.fingerprints.lock().map_err(|_| Error::msg("memory filter lock poisoned"))
+    }
+}
+
+#[async_trait]
+impl Filter for MemoryFilter {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn from_settings(_settings: &Settings, _redis_client: RedisClient) -> Self {
+        MemoryFilter::shared()
+    }
+
+    async fn contains(&mut self, item: &[u8]) -> Result<bool> {
+        Ok(self.fingerprints()?.contains(item))
+    }
+
+    async fn insert_and_check(&mut self, item: &[u8]) -> Result<bool> {
+        Ok(!self.fingerprints()?.insert(item.to_vec()))
+    }
+
+    async fn clear(&mut self) -> Result<()> {
+        self.fingerprints()?.clear();
+        Ok(())
+    }
+}
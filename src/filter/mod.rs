@@ -1,19 +1,103 @@
-use std::any::Any;
-use crate::config::Settings;
+use anyhow::Result;
+use async_trait::async_trait;
+use ::redis::Client as RedisClient;
+use crate::config::{Settings, FilterKind};
 
 mod redis;
+mod memory;
+mod window;
 
+pub use self::redis::RedisBloomFilter;
+pub use memory::MemoryFilter;
+pub use window::WindowFilter;
 
-pub trait Filter: Any + Send + Sync {
+/// Filter fingerprints items, e.g. block hashes, to skip duplicate work
+#[async_trait]
+pub trait Filter: Send + Sync {
     /// filter name
     fn name(&self) -> &'static str;
 
-    /// load settings
-    fn build(&self,settings: &Settings) -> Box<dyn Filter>;
+    /// build the filter from the `[filter]` section
+    fn from_settings(settings: &Settings, redis_client: RedisClient) -> Self where Self: Sized;
 
-    /// fingerprint
-    fn fingerprint(self) -> bool;
+    /// whether `item` has (probably) been inserted
+    async fn contains(&mut self, item: &[u8]) -> Result<bool>;
 
-    /// clear filter collections
-    fn clear(self) -> bool;
-}
\ No newline at end of file
+    /// insert `item`, returns whether it had (probably) been inserted before
+    async fn insert_and_check(&mut self, item: &[u8]) -> Result<bool>;
+
+    /// insert `item`
+    async fn insert(&mut self, item: &[u8]) -> Result<()> {
+        self.insert_and_check(item).await?;
+        Ok(())
+    }
+
+    /// clear all fingerprints
+    async fn clear(&mut self) -> Result<()>;
+}
+
+/// filter selected by the `[filter]` section
+pub fn build(settings: &Settings, redis_client: RedisClient) -> Box<dyn Filter> {
+    match settings.filter.kind {
+        FilterKind::Bloom => Box::new(RedisBloomFilter::from_settings(settings, redis_client)),
+        FilterKind::Memory => Box::new(MemoryFilter::from_settings(settings, redis_client)),
+        FilterKind::Window => Box::new(WindowFilter::from_settings(settings, redis_client)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// behaviour every filter shares, `filter` starts empty after `clear`
+    async fn conformance(filter: &mut dyn Filter) {
+        let name = filter.name();
+        filter.clear().await.unwrap();
+        assert!(!filter.contains(b"a").await.unwrap(), "{} contains before insert", name);
+        assert!(!filter.insert_and_check(b"a").await.unwrap(), "{} first insert", name);
+        assert!(filter.contains(b"a").await.unwrap(), "{} contains after insert", name);
+        assert!(filter.insert_and_check(b"a").await.unwrap(), "{} second insert", name);
+
+        filter.insert(b"b").await.unwrap();
+        assert!(filter.contains(b"b").await.unwrap(), "{} contains after insert", name);
+        assert!(!filter.contains(b"c").await.unwrap(), "{} contains other item", name);
+
+        filter.clear().await.unwrap();
+        assert!(!filter.contains(b"a").await.unwrap(), "{} contains after clear", name);
+        assert!(!filter.contains(b"b").await.unwrap(), "{} contains after clear", name);
+    }
+
+    /// redis of the redis backed filters, they are skipped unless `REDIS_TEST_URL` is set
+    fn test_redis() -> Option<RedisClient> {
+        env::var("REDIS_TEST_URL").ok().map(|url| RedisClient::open(url).unwrap())
+    }
+
+    #[tokio::test]
+    async fn memory_filter() {
+        conformance(&mut MemoryFilter::new()).await;
+    }
+
+    #[tokio::test]
+    async fn memory_filter_is_shared_by_the_process() {
+        let mut first = MemoryFilter::shared();
+        let mut second = MemoryFilter::shared();
+        first.insert(b"memory_filter_is_shared_by_the_process").await.unwrap();
+        assert!(second.contains(b"memory_filter_is_shared_by_the_process").await.unwrap());
+        assert!(!MemoryFilter::new().contains(b"memory_filter_is_shared_by_the_process").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bloom_filter() {
+        if let Some(redis_client) = test_redis() {
+            conformance(&mut RedisBloomFilter::new(redis_client, "explorer:test:bloom", 1000, 0.001)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn window_filter() {
+        if let Some(redis_client) = test_redis() {
+            conformance(&mut WindowFilter::new(redis_client, "explorer:test:window", 60, 6)).await;
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::{Client as RedisClient, aio::Connection, AsyncCommands};
+use substrate_subxt::sp_core::hashing::blake2_256;
+use std::convert::TryInto;
+use crate::config::{Settings, FILTER_KEY};
+use super::Filter;
+
+/// max bits of a redis bitmap
+const MAX_BITS: u64 = 1 << 32;
+
+/// RedisBloomFilter is a bloom filter stored in a plain redis bitmap, no redis module required
+pub struct RedisBloomFilter {
+    pub redis_client: RedisClient,
+    pub key: String,
+    /// bits of the bitmap
+    pub bits: u64,
+    /// hash functions per item
+    pub hashes: u32,
+    connection: Option<Connection>,
+}
+
+impl RedisBloomFilter {
+    /// filter sized for `capacity` items with `error_rate` false positive rate
+    pub fn new(redis_client: RedisClient, key: &str, capacity: u64, error_rate: f64) -> RedisBloomFilter {
+        let (bits, hashes) = optimal_size(capacity, error_rate);
+        RedisBloomFilter {
+            redis_client,
+            key: key.to_string(),
+            bits,
+            hashes,
+            connection: None,
+        }
+    }
+
+    async fn connection(&mut self) -> Result<&mut Connection> {
+        if self.connection.is_none() {
+            self.connection = Some(self.redis_client.get_async_connection().await?);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    /// bit offsets of `item` by double hashing
+    fn offsets(&self, item: &[u8]) -> Vec<u64> {
+        let hash = blake2_256(item);
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap());
+        (0..self.hashes as u64)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bits)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Filter for RedisBloomFilter {
+    fn name(&self) -> &'static str {
+        "bloom"
+    }
+
+    fn from_settings(settings: &Settings, redis_client: RedisClient) -> Self {
+        RedisBloomFilter::new(
+            redis_client,
+            &format!("{}:{}", FILTER_KEY, settings.chain.name),
+            settings.filter.capacity,
+            settings.filter.error_rate,
+        )
+    }
+
+    async fn contains(&mut self, item: &[u8]) -> Result<bool> {
+        let mut pipe = redis::pipe();
+        for offset in self.offsets(item) {
+            pipe.getbit(&self.key, offset as usize);
+        }
+        let bits: Vec<bool> = pipe.query_async(self.connection().await?).await?;
+        Ok(bits.into_iter().all(|bit| bit))
+    }
+
+    async fn insert_and_check(&mut self, item: &[u8]) -> Result<bool> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for offset in self.offsets(item) {
+            pipe.setbit(&self.key, offset as usize, true);
+        }
+        let previous: Vec<bool> = pipe.query_async(self.connection().await?).await?;
+        Ok(previous.into_iter().all(|bit| bit))
+    }
+
+    async fn clear(&mut self) -> Result<()> {
+        let key = self.key.clone();
+        self.connection().await?.del(key).await?;
+        Ok(())
+    }
+}
+
+/// bitmap bits and hash functions for `capacity` items at `error_rate`,
+/// a bitmap larger than redis allows is capped with a warning of the error rate actually reached
+fn optimal_size(capacity: u64, error_rate: f64) -> (u64, u32) {
+    let capacity = capacity.max(1) as f64;
+    let error_rate = error_rate.max(f64::MIN_POSITIVE).min(0.5);
+    let ln2 = std::f64::consts::LN_2;
+    let optimal = (-capacity * error_rate.ln() / (ln2 * ln2)).ceil().max(8.0);
+    let bits = optimal.min(MAX_BITS as f64) as u64;
+    let hashes = ((bits as f64 / capacity) * ln2).round().max(1.0) as u32;
+    if optimal > MAX_BITS as f64 {
+        llog::warn!("bloom filter needs {} bits for {} items at error rate {}, capped to {} bits with error rate {:.6}",
+                    optimal, capacity, error_rate, bits, false_positive_rate(bits, hashes, capacity));
+    }
+    (bits, hashes)
+}
+
+/// false positive rate of `bits` and `hashes` holding `capacity` items
+fn false_positive_rate(bits: u64, hashes: u32, capacity: f64) -> f64 {
+    (1.0 - (-(hashes as f64) * capacity / bits as f64).exp()).powi(hashes as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(capacity: u64, error_rate: f64) -> RedisBloomFilter {
+        RedisBloomFilter::new(RedisClient::open("redis://127.0.0.1/").unwrap(), "test", capacity, error_rate)
+    }
+
+    #[test]
+    fn default_size() {
+        let (bits, hashes) = optimal_size(100_000_000, 0.001);
+        // ~180MB bitmap
+        assert_eq!(bits, 1_437_758_757);
+        assert_eq!(hashes, 10);
+        assert!(false_positive_rate(bits, hashes, 1e8) <= 0.00101);
+    }
+
+    #[test]
+    fn size_bounds() {
+        assert_eq!(optimal_size(0, 0.001), optimal_size(1, 0.001));
+        assert_eq!(optimal_size(1, 0.5).0, 8);
+        let (bits, hashes) = optimal_size(10_000_000_000, 0.001);
+        assert_eq!(bits, MAX_BITS);
+        assert!(hashes >= 1);
+        // an invalid error rate still sizes a usable filter
+        let (bits, hashes) = optimal_size(1000, 0.0);
+        assert!(bits <= MAX_BITS && hashes >= 1);
+    }
+
+    #[test]
+    fn offsets_are_stable_and_in_range() {
+        let filter = filter(1000, 0.01);
+        let offsets = filter.offsets(b"0x01");
+        assert_eq!(offsets.len(), filter.hashes as usize);
+        assert!(offsets.iter().all(|offset| *offset < filter.bits));
+        assert_eq!(offsets, filter.offsets(b"0x01"));
+        assert_ne!(offsets, filter.offsets(b"0x02"));
+    }
+}
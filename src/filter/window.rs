@@ -0,0 +1,108 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::{Client as RedisClient, aio::Connection, Script, AsyncCommands};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::config::{Settings, FILTER_KEY};
+use super::Filter;
+
+/// checks every bucket in KEYS, then adds ARGV[1] to the current bucket KEYS[1] expiring in ARGV[2] seconds
+const INSERT_SCRIPT: &'static str = r#"
+local seen = 0
+for _, key in ipairs(KEYS) do
+    if redis.call('SISMEMBER', key, ARGV[1]) == 1 then
+        seen = 1
+        break
+    end
+end
+redis.call('SADD', KEYS[1], ARGV[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return seen
+"#;
+
+/// WindowFilter remembers items for a sliding time window only, in redis sets of `bucket` seconds
+/// that expire on their own, so memory stays bounded by the items inserted within the window
+pub struct WindowFilter {
+    pub redis_client: RedisClient,
+    pub key: String,
+    /// window length in seconds
+    pub window: u64,
+    /// bucket length in seconds
+    pub bucket: u64,
+    connection: Option<Connection>,
+}
+
+impl WindowFilter {
+    /// filter remembering items for `window` seconds, split into `buckets` sets
+    pub fn new(redis_client: RedisClient, key: &str, window: u64, buckets: u64) -> WindowFilter {
+        let window = window.max(1);
+        WindowFilter {
+            redis_client,
+            key: key.to_string(),
+            window,
+            bucket: (window / buckets.max(1)).max(1),
+            connection: None,
+        }
+    }
+
+    async fn connection(&mut self) -> Result<&mut Connection> {
+        if self.connection.is_none() {
+            self.connection = Some(self.redis_client.get_async_connection().await?);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    /// bucket keys covering the window, current bucket first
+    fn bucket_keys(&self) -> Vec<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let current = now / self.bucket;
+        let count = (self.window + self.bucket - 1) / self.bucket;
+        (0..count)
+            .filter_map(|i| current.checked_sub(i))
+            .map(|bucket| format!("{}:{}", self.key, bucket))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Filter for WindowFilter {
+    fn name(&self) -> &'static str {
+        "window"
+    }
+
+    fn from_settings(settings: &Settings, redis_client: RedisClient) -> Self {
+        WindowFilter::new(
+            redis_client,
+            &format!("{}:{}:window", FILTER_KEY, settings.chain.name),
+            settings.filter.window,
+            settings.filter.buckets,
+        )
+    }
+
+    async fn contains(&mut self, item: &[u8]) -> Result<bool> {
+        let mut pipe = redis::pipe();
+        for key in self.bucket_keys() {
+            pipe.sismember(key, item);
+        }
+        let seen: Vec<bool> = pipe.query_async(self.connection().await?).await?;
+        Ok(seen.into_iter().any(|seen| seen))
+    }
+
+    async fn insert_and_check(&mut self, item: &[u8]) -> Result<bool> {
+        let keys = self.bucket_keys();
+        // the current bucket outlives the window by one bucket so the oldest bucket checked is still complete
+        let ttl = self.window + self.bucket;
+        let mut script = Script::new(INSERT_SCRIPT).prepare_invoke();
+        for key in keys.iter() {
+            script.key(key);
+        }
+        script.arg(item).arg(ttl);
+        let seen: bool = script.invoke_async(self.connection().await?).await?;
+        Ok(seen)
+    }
+
+    async fn clear(&mut self) -> Result<()> {
+        let keys = self.bucket_keys();
+        self.connection().await?.del(keys).await?;
+        Ok(())
+    }
+}
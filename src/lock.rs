@@ -0,0 +1,45 @@
+use anyhow::Result;
+use redis::{aio::Connection, Script};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// deletes the lock only if it still holds the token of the releasing owner.
+/// KEYS: lock. ARGV: token
+const RELEASE_SCRIPT: &'static str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// RedisLock is held by one process at a time, it expires after its ttl if the owner never releases it
+pub struct RedisLock {
+    pub key: String,
+    token: String,
+}
+
+impl RedisLock {
+    /// take the lock for up to `ttl`, `None` if another process holds it
+    pub async fn acquire(con: &mut Connection, key: &str, ttl: Duration) -> Result<Option<RedisLock>> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let token = format!("{}:{}", std::process::id(), nanos);
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(con)
+            .await?;
+        Ok(acquired.map(|_| RedisLock { key: key.to_string(), token }))
+    }
+
+    /// returns false if the lock expired before, another process may hold it now
+    pub async fn release(self, con: &mut Connection) -> Result<bool> {
+        let released: u8 = Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .invoke_async(con)
+            .await?;
+        Ok(released > 0)
+    }
+}
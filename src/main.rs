@@ -12,6 +12,10 @@ mod scheduler;
 mod collections;
 pub mod config;
 mod db;
+mod decoder;
+mod checkpoint;
+mod reorg;
+mod lock;
 
 
 use anyhow::Result;
@@ -20,7 +24,12 @@ use std::env;
 use tokio::time::Duration;
 use crate::cmd::producer::Producer;
 use crate::cmd::consumer::Consumer;
-use crate::config::{QUEUE_NAME, CELERY_HEARTBEAT, CONFIG_FILE, REDIS_TIMEOUT, Settings, ExplorerLog, AppState};
+use crate::cmd::backfill::Backfill;
+use crate::cmd::dead_letters::{DeadLetterCmd, DEFAULT_LIMIT};
+use crate::cmd::storage::StorageCmd;
+use crate::config::{QUEUE_NAME, CELERY_HEARTBEAT, CONFIG_FILE, REDIS_TIMEOUT, Settings, ExplorerLog, AppState,
+                    DECODE_CHUNK_SIZE, BACKFILL_PRIORITY, SinkKind};
+use clap::value_t;
 use redis::ConnectionLike;
 
 
@@ -35,13 +44,6 @@ async fn main() -> Result<()> {
 
     ExplorerLog::init(&settings);
     let state = AppState::new(&settings).await?;
-    let meili_client_state = state.meili_client.is_healthy().await;
-    if !meili_client_state {
-        llog::error!("Could not ping meilisearch server to address {} with apikey: {}",
-                     &settings.meilisearch.host,
-                     &settings.meilisearch.apikey);
-        std::process::exit(101);
-    }
 
     let mut redis_con = state.redis_client.get_connection_with_timeout(REDIS_TIMEOUT)?;
 
@@ -59,8 +61,63 @@ async fn main() -> Result<()> {
             Producer::start(&state).await
         }
         ("consumer", Some(matches)) => {
+            init_sink(&settings, &state).await?;
             Consumer::start(&state).await
         }
+        ("backfill", Some(matches)) => {
+            init_sink(&settings, &state).await?;
+            let from = value_t!(matches, "from", u64)?;
+            let to = value_t!(matches, "to", u64)?;
+            let chunk_size = if matches.is_present("chunk-size") {
+                value_t!(matches, "chunk-size", u64)?
+            } else {
+                DECODE_CHUNK_SIZE
+            };
+            let priority = if matches.is_present("priority") {
+                value_t!(matches, "priority", i32)?
+            } else {
+                BACKFILL_PRIORITY
+            };
+            Backfill::start(&state, from, to, chunk_size, priority).await
+        }
+        ("dead-letters", Some(matches)) => match matches.subcommand() {
+            ("list", Some(matches)) => {
+                let start = if matches.is_present("start") {
+                    value_t!(matches, "start", usize)?
+                } else {
+                    0
+                };
+                let limit = if matches.is_present("limit") {
+                    value_t!(matches, "limit", usize)?
+                } else {
+                    DEFAULT_LIMIT
+                };
+                DeadLetterCmd::list(&state, start, limit).await
+            }
+            ("inspect", Some(matches)) => {
+                DeadLetterCmd::inspect(&state, value_t!(matches, "index", usize)?).await
+            }
+            ("requeue", Some(matches)) => {
+                let index = if matches.is_present("all") {
+                    None
+                } else {
+                    Some(value_t!(matches, "index", usize)?)
+                };
+                DeadLetterCmd::requeue(&state, index).await
+            }
+            _ => unreachable!(),
+        },
+        ("storage", Some(matches)) => {
+            let module = matches.value_of("module").unwrap_or_default();
+            let item = matches.value_of("item").unwrap_or_default();
+            let keys: Vec<&str> = matches.values_of("key").map(|keys| keys.collect()).unwrap_or_default();
+            let block = if matches.is_present("block") {
+                Some(value_t!(matches, "block", u64)?)
+            } else {
+                None
+            };
+            StorageCmd::get(&state, module, item, &keys, block).await
+        }
         _ => unreachable!(),
     };
     if let Err(e) = res {
@@ -68,4 +125,18 @@ async fn main() -> Result<()> {
         std::process::exit(101);
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// check the sink is reachable and prepare it, only for the commands writing blocks
+async fn init_sink(settings: &Settings, state: &AppState<'_>) -> Result<()> {
+    if settings.sink.kind == SinkKind::Meilisearch {
+        let meili_client_state = state.meili_client.is_healthy().await;
+        if !meili_client_state {
+            llog::error!("Could not ping meilisearch server to address {} with apikey: {}",
+                         &settings.meilisearch.host,
+                         &settings.meilisearch.apikey);
+            std::process::exit(101);
+        }
+    }
+    db::sink(settings, &state.meili_client, &state.redis_client)?.init().await
+}
@@ -0,0 +1,64 @@
+use anyhow::{Result, Error};
+use redis::{aio::Connection, AsyncCommands};
+use crate::config::UNFINALIZED_KEY;
+
+/// ForkTracker records hashes of indexed non-finalized blocks by redis, one per chain and runtime
+pub struct ForkTracker {
+    pub redis_connection: Connection,
+    pub key: String,
+}
+
+impl ForkTracker {
+    pub fn new(redis_connection: Connection, chain: &str, runtime: &str) -> ForkTracker {
+        ForkTracker {
+            redis_connection,
+            key: format!("{}:{}:{}", UNFINALIZED_KEY, chain, runtime),
+        }
+    }
+
+    /// hash of the indexed block at `number`
+    pub async fn hash(&mut self, number: u64) -> Result<Option<String>> {
+        let res = self.redis_connection.hget(&self.key, number).await?;
+        Ok(res)
+    }
+
+    pub async fn set(&mut self, number: u64, hash: &str) -> Result<()> {
+        self.redis_connection.hset(&self.key, number, hash).await?;
+        Ok(())
+    }
+
+    /// sorted numbers of indexed non-finalized blocks
+    pub async fn numbers(&mut self) -> Result<Vec<u64>> {
+        let fields: Vec<String> = self.redis_connection.hkeys(&self.key).await?;
+        let mut numbers = fields.iter()
+            .map(|field| field.parse::<u64>().map_err(|_| Error::msg("unfinalized block number decode error")))
+            .collect::<Result<Vec<_>>>()?;
+        numbers.sort();
+        Ok(numbers)
+    }
+
+    /// highest indexed non-finalized block
+    pub async fn tip(&mut self) -> Result<Option<u64>> {
+        Ok(self.numbers().await?.last().cloned())
+    }
+
+    /// forget blocks finality has caught up with
+    pub async fn prune(&mut self, finalized: u64) -> Result<()> {
+        let numbers: Vec<u64> = self.numbers().await?.into_iter().filter(|n| *n <= finalized).collect();
+        self.remove(&numbers).await
+    }
+
+    /// forget blocks above fork point and return them
+    pub async fn truncate(&mut self, fork: u64) -> Result<Vec<u64>> {
+        let numbers: Vec<u64> = self.numbers().await?.into_iter().filter(|n| *n > fork).collect();
+        self.remove(&numbers).await?;
+        Ok(numbers)
+    }
+
+    async fn remove(&mut self, numbers: &[u64]) -> Result<()> {
+        if !numbers.is_empty() {
+            self.redis_connection.hdel(&self.key, numbers).await?;
+        }
+        Ok(())
+    }
+}
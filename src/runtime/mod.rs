@@ -1,12 +1,35 @@
 cfg_if::cfg_if! {
     if #[cfg(feature = "kusama")] {
+        mod relay;
         pub mod kusama;
         pub use kusama::Runtime;
+        pub const RUNTIME_NAME: &'static str = "kusama";
+    } else if #[cfg(feature = "polkadot")] {
+        mod relay;
+        pub mod polkadot;
+        pub use polkadot::Runtime;
+        pub const RUNTIME_NAME: &'static str = "polkadot";
+    } else if #[cfg(feature = "westend")] {
+        mod relay;
+        pub mod westend;
+        pub use westend::Runtime;
+        pub const RUNTIME_NAME: &'static str = "westend";
     } else if #[cfg(feature = "node_template")]  {
         pub mod node_template;
         pub use node_template::Runtime;
+        pub const RUNTIME_NAME: &'static str = "node_template";
     } else {
         pub mod node_template;
         pub use node_template::Runtime;
+        pub const RUNTIME_NAME: &'static str = "node_template";
     }
-}
\ No newline at end of file
+}
+
+use substrate_subxt::ClientBuilder;
+
+/// register event argument types the runtime does not know about
+pub fn register_type_sizes(builder: ClientBuilder<Runtime>) -> ClientBuilder<Runtime> {
+    #[cfg(any(feature = "kusama", feature = "polkadot", feature = "westend"))]
+    let builder = relay::register_relay_type_sizes(builder);
+    builder
+}
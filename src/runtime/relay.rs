@@ -0,0 +1,28 @@
+use substrate_subxt::{ClientBuilder, Runtime};
+
+/// event argument types of relay chain pallets not covered by the upstream runtimes
+pub fn register_relay_type_sizes<T: Runtime>(builder: ClientBuilder<T>) -> ClientBuilder<T> {
+    builder
+        .register_type_size::<u32>("ParaId")
+        .register_type_size::<u32>("CoreIndex")
+        .register_type_size::<u32>("GroupIndex")
+        .register_type_size::<Vec<u8>>("HeadData")
+        .register_type_size::<u32>("LeasePeriod")
+        .register_type_size::<u32>("AuctionIndex")
+        .register_type_size::<u32>("BountyIndex")
+        .register_type_size::<u32>("ProposalIndex")
+        .register_type_size::<u32>("ReferendumIndex")
+        .register_type_size::<u32>("PropIndex")
+        .register_type_size::<u32>("RegistrarIndex")
+        .register_type_size::<u32>("MemberCount")
+        .register_type_size::<u32>("EraIndex")
+        .register_type_size::<u32>("AccountIndex")
+        .register_type_size::<u8>("VoteThreshold")
+        .register_type_size::<u8>("ElectionCompute")
+        .register_type_size::<u8>("ProxyType")
+        .register_type_size::<[u8; 16]>("Kind")
+        .register_type_size::<[u8; 32]>("CallHash")
+        .register_type_size::<Vec<u8>>("OpaqueTimeSlot")
+        .register_type_size::<(u32, u32)>("TaskAddress<BlockNumber>")
+        .register_type_size::<(u32, u32)>("Timepoint<BlockNumber>")
+}
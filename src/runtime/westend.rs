@@ -0,0 +1,57 @@
+use substrate_subxt::{
+    balances::{AccountData, Balances, BalancesEventTypeRegistry},
+    session::{Session, SessionEventTypeRegistry},
+    staking::{Staking, StakingEventTypeRegistry},
+    sudo::{Sudo, SudoEventTypeRegistry},
+    system::{System, SystemEventTypeRegistry},
+    register_default_type_sizes, BasicSessionKeys, DefaultExtra, EventTypeRegistry,
+};
+use substrate_subxt::sp_core::H256;
+use substrate_subxt::sp_runtime::{
+    generic::Header,
+    traits::{BlakeTwo256, IdentifyAccount, Verify},
+    MultiAddress, MultiSignature, OpaqueExtrinsic,
+};
+
+/// Runtime is the westend test relay chain runtime
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Runtime;
+
+impl substrate_subxt::Runtime for Runtime {
+    type Signature = MultiSignature;
+    type Extra = DefaultExtra<Self>;
+
+    fn register_type_sizes(event_type_registry: &mut EventTypeRegistry<Self>) {
+        event_type_registry.with_system();
+        event_type_registry.with_balances();
+        event_type_registry.with_session();
+        event_type_registry.with_staking();
+        event_type_registry.with_sudo();
+        register_default_type_sizes(event_type_registry);
+    }
+}
+
+impl System for Runtime {
+    type Index = u32;
+    type BlockNumber = u32;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = <<MultiSignature as Verify>::Signer as IdentifyAccount>::AccountId;
+    type Address = MultiAddress<Self::AccountId, ()>;
+    type Header = Header<Self::BlockNumber, BlakeTwo256>;
+    type Extrinsic = OpaqueExtrinsic;
+    type AccountData = AccountData<<Self as Balances>::Balance>;
+}
+
+impl Balances for Runtime {
+    type Balance = u128;
+}
+
+impl Session for Runtime {
+    type ValidatorId = <Self as System>::AccountId;
+    type Keys = BasicSessionKeys;
+}
+
+impl Staking for Runtime {}
+
+impl Sudo for Runtime {}
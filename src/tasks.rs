@@ -1,12 +1,39 @@
 use celery::task::TaskResult;
-use substrate_subxt::{ClientBuilder};
-use redis::ConnectionLike;
+use celery::Celery;
+use celery::broker::RedisBroker;
+use substrate_subxt::{ClientBuilder, Client};
+use redis::{ConnectionLike, AsyncCommands};
 use anyhow::{Result, Error};
 
-use crate::runtime::Runtime;
-use crate::config::{AppState, REDIS_TIMEOUT, Settings};
+use crate::runtime::{self, Runtime, RUNTIME_NAME};
+use crate::config::{AppState, REDIS_TIMEOUT, Settings, CONFIG_FILE, QUEUE_NAME, PULL_BATCH_SIZE, DECODE_CHUNK_SIZE,
+                    BLOCK_QUEUE_KEY, TIP_PRIORITY, VISIBILITY_TIMEOUT, POP_TIMEOUT, DISPATCH_TIMEOUT, CHUNKS_PER_TASK,
+                    MAX_INFLIGHT_CHUNKS, PULL_LOCK_KEY, PULL_LOCK_TTL,
+                    BLOCK_RETRY_KEY, RETRY_BASE_DELAY, RETRY_MAX_DELAY};
+use crate::decoder::BlockDecoder;
+use crate::config::DecoderMode;
+use crate::db;
+use crate::db::Sink;
+use crate::checkpoint::CheckpointStore;
+use crate::reorg::ForkTracker;
+use crate::lock::RedisLock;
+use crate::collections::{HasAsyncPriorityQueue, HasReliableQueue, RedisPriorityQueue, Release, backoff};
+use crate::filter::{self, Filter};
+use codec::{Encode, Decode};
 use celery::prelude::*;
 use std::env;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// BlockRange is a chunk of blocks waiting in the block priority queue
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub(crate) struct BlockRange {
+    pub from: u64,
+    pub to: u64,
+    pub priority: i32,
+    /// advance the sync checkpoint once indexed, false for backfill
+    pub checkpoint: bool,
+}
 
 #[celery::task]
 pub(crate) fn add(x: i32, y: i32) -> TaskResult<i32> {
@@ -21,24 +48,439 @@ pub(crate) fn long_running_task(secs: Option<u64>) -> TaskResult<()> {
     unimplemented!()
 }
 
+/// dispatch decode_block tasks for blocks not processed yet, skipped while another pull runs
 #[celery::task]
 pub(crate) async fn pull() -> TaskResult<()> {
-    let client = ClientBuilder::<Runtime>::new()
-        .set_url(std::env::var("CHAIN_RPC_URL").unwrap_or_else(|_| "ws://127.0.0.1:9944".into()))
-        .build()
-        .await.with_unexpected_err(|| {
-        "Chain node server error"
+    let settings = load_settings()?;
+    let state = AppState::new(&settings).await.map_err(unexpected)?;
+
+    let mut redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
     })?;
+    let lock_key = format!("{}:{}", PULL_LOCK_KEY, settings.chain.name);
+    let lock = match RedisLock::acquire(&mut redis_con, &lock_key, PULL_LOCK_TTL).await.map_err(unexpected)? {
+        Some(lock) => lock,
+        None => {
+            llog::info!("skip pull, the previous one is still running");
+            return Ok(());
+        }
+    };
+    let res = pull_blocks(&settings, &state).await;
+    if !lock.release(&mut redis_con).await.map_err(unexpected)? {
+        llog::warn!("pull took longer than its {:?} lock", PULL_LOCK_TTL);
+    }
+    res
+}
 
+async fn pull_blocks(settings: &Settings, state: &AppState<'_>) -> TaskResult<()> {
+    let mut decoder = block_decoder(settings, state).await?;
+    let client = &decoder.client;
 
     let finalized_head = client.finalized_head().await.with_unexpected_err(|| {
         "get chain node server finalized head error"
     })?;
 
-    let finalized_block_number = client.block(Some(finalized_head)).await.with_unexpected_err(|| {
+    let finalized_block = client.block(Some(finalized_head)).await.with_unexpected_err(|| {
         "get chain node server finalized head error"
+    })?.ok_or_else(|| TaskError::UnexpectedError("finalized head block not found".into()))?;
+    let finalized_block_number = finalized_block.block.header.number as u64;
+
+    let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
+    })?;
+    let mut checkpoints = CheckpointStore::new(redis_con, &settings.chain.name, RUNTIME_NAME);
+    let checkpoint = checkpoints.load().await.map_err(unexpected)?.map(|c| c.number);
+    let dispatched = checkpoints.dispatched().await.map_err(unexpected)?;
+    for (from, to) in checkpoints.dead_chunks().await.map_err(unexpected)? {
+        llog::error!("checkpoint is stuck below dead lettered block chunk #{}..=#{}, \
+                      requeue it with `explorer dead-letters requeue`", from, to);
+    }
+
+    if settings.chain.follow_best_head {
+        follow_best_head(&mut decoder, state, settings, finalized_block_number).await?;
+    }
+
+    let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
+    })?;
+    let mut queue = RedisPriorityQueue::new(redis_con, BLOCK_QUEUE_KEY)
+        .reliable(&worker_id(), VISIBILITY_TIMEOUT)
+        .max_retries(settings.queue.max_retries);
+    let dispatcher = dispatcher().await?;
+
+    // chunks of crashed consumers
+    let requeued = queue.requeue_expired().await.map_err(unexpected)?;
+    if requeued > 0 {
+        llog::warn!("requeue {} expired block chunks", requeued);
+    }
+    for _ in 0..decode_tasks(requeued) {
+        dispatcher.send_task(decode_block::new()).await.with_unexpected_err(|| {
+            "send decode_block task error"
+        })?;
+    }
+
+    // gaps below the dispatched cursor, chunks that never completed and are neither queued nor waiting for a retry
+    let deadlines = queue.reliable.as_ref().map(|reliable| reliable.deadlines.clone()).unwrap_or_default();
+    let mut dispatch = checkpoints.stale(DISPATCH_TIMEOUT, &deadlines, &[BLOCK_QUEUE_KEY, BLOCK_RETRY_KEY], |from, to| {
+        BlockRange { from, to, priority: TIP_PRIORITY, checkpoint: true }.encode()
+    }).await.map_err(unexpected)?;
+    if !dispatch.is_empty() {
+        llog::warn!("dispatch {} stale block chunks again", dispatch.len());
+    }
+
+    // the cursor only moves while consumers keep up
+    let inflight = checkpoints.inflight().await.map_err(unexpected)?;
+    let capacity = MAX_INFLIGHT_CHUNKS.saturating_sub(inflight) as u64 * DECODE_CHUNK_SIZE;
+    let range = decode_range(checkpoint.max(dispatched), finalized_block_number)
+        .and_then(|range| limit_range(range, capacity));
+    if capacity == 0 {
+        llog::info!("{} block chunks in flight, wait for consumers before dispatching more", inflight);
+    }
+    if let Some(range) = range.as_ref() {
+        llog::info!("dispatch block range #{}..=#{}", range.start(), range.end());
+        let new_chunks = chunks(range, DECODE_CHUNK_SIZE);
+        checkpoints.track(&new_chunks).await.map_err(unexpected)?;
+        dispatch.extend(new_chunks);
+    }
+    if dispatch.is_empty() {
+        return Ok(());
+    }
+
+    let block_ranges: Vec<Box<BlockRange>> = dispatch.into_iter()
+        .map(|(from, to)| Box::new(BlockRange { from, to, priority: TIP_PRIORITY, checkpoint: true }))
+        .collect();
+    queue.push_many(&block_ranges, Some(TIP_PRIORITY)).await.map_err(unexpected)?;
+    for _ in 0..decode_tasks(block_ranges.len()) {
+        dispatcher.send_task(decode_block::new()).await.with_unexpected_err(|| {
+            "send decode_block task error"
+        })?;
+    }
+    if let Some(range) = range {
+        checkpoints.set_dispatched(*range.end()).await.map_err(unexpected)?;
+    }
+
+    Ok(())
+}
+
+/// pop up to `CHUNKS_PER_TASK` block chunks by priority, decode them and write them to the sink.
+/// failed chunks are retried with backoff through `BLOCK_RETRY_KEY`, not by celery
+#[celery::task(max_retries = 3)]
+pub(crate) async fn decode_block() -> TaskResult<()> {
+    let settings = load_settings()?;
+    let state = AppState::new(&settings).await.map_err(unexpected)?;
+
+    let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
     })?;
-    //TODO: add block decode range
+    let mut queue = RedisPriorityQueue::new(redis_con, BLOCK_QUEUE_KEY)
+        .reliable(&worker_id(), VISIBILITY_TIMEOUT)
+        .max_retries(settings.queue.max_retries);
+    // wait for the first chunk only, the chunks of this task may have been taken by others already
+    let mut next: Option<Box<BlockRange>> = queue.pop_blocking(POP_TIMEOUT).await.map_err(unexpected)?;
+    if next.is_none() {
+        llog::debug!("block queue {} is empty", BLOCK_QUEUE_KEY);
+        return Ok(());
+    }
+
+    let mut decoder = block_decoder(&settings, &state).await?;
+    let mut filter = filter::build(&settings, state.redis_client.clone());
+    let mut indexed = 0;
+    while let Some(range) = next {
+        match index_range(&settings, &state, &mut decoder, filter.as_mut(), &range).await {
+            Ok(()) => queue.ack(&range).await.map_err(unexpected)?,
+            Err(e) => retry(&settings, &state, &mut queue, &range, &e.to_string()).await?,
+        }
+        indexed += 1;
+        // reserve the next chunk only now, its visibility timeout must not run while others are indexed
+        next = if indexed < CHUNKS_PER_TASK {
+            queue.pop_many(1).await.map_err(unexpected)?.pop()
+        } else {
+            None
+        };
+    }
+
+    Ok(())
+}
+
+/// put a failed chunk in the retry queue until its backoff passed, or dead letter it
+async fn retry(settings: &Settings, state: &AppState<'_>, queue: &mut RedisPriorityQueue<'_>, range: &Box<BlockRange>, error: &str) -> TaskResult<()> {
+    let attempts = queue.attempts(range).await.map_err(unexpected)?;
+    let delay = backoff(attempts.saturating_sub(1), RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+    match queue.nack_delayed(range, BLOCK_RETRY_KEY, delay, error).await.map_err(unexpected)? {
+        Release::Requeued => {
+            llog::warn!("block chunk #{}..=#{} failed, retry in {:?}: {}", range.from, range.to, delay, error);
+        }
+        Release::Dead if range.checkpoint => {
+            llog::error!("block chunk #{}..=#{} dead lettered, the checkpoint stops below it until it is requeued",
+                         range.from, range.to);
+            let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+                "redis server error"
+            })?;
+            CheckpointStore::new(redis_con, &settings.chain.name, RUNTIME_NAME)
+                .dead(range.from, range.to).await.map_err(unexpected)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn index_range(settings: &Settings, state: &AppState<'_>, decoder: &mut BlockDecoder, filter: &mut dyn Filter,
+                     range: &BlockRange) -> TaskResult<()> {
+    let sink = db::sink(settings, &state.meili_client, &state.redis_client).map_err(unexpected)?;
+    let mut blocks = Vec::new();
+    let mut last_hash = None;
+    for number in range.from..=range.to {
+        let hash = decoder.client.block_hash(Some(number.into())).await.with_unexpected_err(|| {
+            "get chain node server block hash error"
+        })?.ok_or_else(|| TaskError::UnexpectedError(format!("block #{} hash not found", number)))?;
+        let hash = format!("{:?}", hash);
+        last_hash = Some(hash.clone());
+        // overlapping ranges are enqueued again when a dispatch times out,
+        // a filter hit is only a hint since the bloom filter has false positives
+        if filter.contains(hash.as_bytes()).await.map_err(unexpected)? {
+            if sink.contains(number, &hash).await.map_err(unexpected)? {
+                llog::debug!("block #{} {} already processed", number, hash);
+                continue;
+            }
+            llog::warn!("block #{} {} is not stored, duplicate filter false positive", number, hash);
+        }
+        blocks.push(decoder.decode_block(number, true).await.map_err(unexpected)?);
+    }
+    sink.write(&blocks).await.map_err(unexpected)?;
+
+    // only block hashes are fingerprinted, blocks are skipped or indexed as a whole
+    for block in blocks.iter() {
+        filter.insert(block.hash.as_bytes()).await.map_err(unexpected)?;
+    }
+
+    if !range.checkpoint {
+        return Ok(());
+    }
+    let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
+    })?;
+    let mut checkpoints = CheckpointStore::new(redis_con, &settings.chain.name, RUNTIME_NAME);
+    if let Some(last_hash) = last_hash {
+        checkpoints.complete(range.from, range.to, &last_hash).await.map_err(unexpected)?;
+    }
+
+    Ok(())
+}
+
+/// index blocks between the finalized head and the best head with `finalized: false`.
+/// a reorg is detected when a new block's parent differs from the indexed block below it,
+/// the stale fork is then removed from the sink and the canonical fork indexed again.
+/// finalized blocks are indexed again by decode_block, which flips the flag, blocks finalized on
+/// another fork than the indexed one are replaced before they are forgotten
+async fn follow_best_head(decoder: &mut BlockDecoder, state: &AppState<'_>, settings: &Settings, finalized: u64) -> TaskResult<()> {
+    let best_head = decoder.client.block_hash(None).await.with_unexpected_err(|| {
+        "get chain node server best head error"
+    })?.ok_or_else(|| TaskError::UnexpectedError("best head not found".into()))?;
+    let best = decoder.client.header(Some(best_head)).await.with_unexpected_err(|| {
+        "get chain node server best head error"
+    })?.ok_or_else(|| TaskError::UnexpectedError("best head header not found".into()))?.number as u64;
+
+    let sink = db::sink(settings, &state.meili_client, &state.redis_client).map_err(unexpected)?;
+    let redis_con = state.redis_client.get_async_connection().await.with_unexpected_err(|| {
+        "redis server error"
+    })?;
+    let mut forks = ForkTracker::new(redis_con, &settings.chain.name, RUNTIME_NAME);
+    reindex_finalized(decoder, sink.as_ref(), &mut forks, finalized).await?;
+    forks.prune(finalized).await.map_err(unexpected)?;
+
+    let mut number = finalized + 1;
+    if let Some(tip) = forks.tip().await.map_err(unexpected)? {
+        number = rollback(&decoder.client, sink.as_ref(), &mut forks, tip, finalized).await? + 1;
+    }
+
+    while number <= best {
+        let block = decoder.decode_block(number, false).await.map_err(unexpected)?;
+        if number - 1 > finalized {
+            let parent = forks.hash(number - 1).await.map_err(unexpected)?;
+            if parent.map_or(false, |hash| hash != block.parent_hash) {
+                number = rollback(&decoder.client, sink.as_ref(), &mut forks, number - 1, finalized).await? + 1;
+                continue;
+            }
+        }
+        sink.write(&[block.clone()]).await.map_err(unexpected)?;
+        forks.set(number, &block.hash).await.map_err(unexpected)?;
+        number += 1;
+    }
 
     Ok(())
 }
+
+/// replace tracked blocks finality has caught up with whose hash differs from the finalized chain,
+/// their stale fork was indexed and the reorg happened below the tracked tip
+async fn reindex_finalized(decoder: &mut BlockDecoder, sink: &dyn Sink, forks: &mut ForkTracker, finalized: u64) -> TaskResult<()> {
+    let numbers: Vec<u64> = forks.numbers().await.map_err(unexpected)?
+        .into_iter().filter(|number| *number <= finalized).collect();
+    for number in numbers {
+        let canonical = decoder.client.block_hash(Some(number.into())).await.with_unexpected_err(|| {
+            "get chain node server block hash error"
+        })?.map(|hash| format!("{:?}", hash));
+        let indexed = forks.hash(number).await.map_err(unexpected)?;
+        if indexed.is_none() || indexed == canonical {
+            continue;
+        }
+        llog::warn!("block #{} was finalized on another fork, index it again", number);
+        sink.remove(&[number]).await.map_err(unexpected)?;
+        let block = decoder.decode_block(number, true).await.map_err(unexpected)?;
+        sink.write(&[block]).await.map_err(unexpected)?;
+    }
+    Ok(())
+}
+
+/// walk back from `from` to the last indexed block still on the canonical chain,
+/// remove the blocks above it and return the fork point
+async fn rollback(client: &Client<Runtime>, sink: &dyn Sink, forks: &mut ForkTracker, from: u64, finalized: u64) -> TaskResult<u64> {
+    let mut fork = from;
+    while fork > finalized {
+        let canonical = client.block_hash(Some(fork.into())).await.with_unexpected_err(|| {
+            "get chain node server block hash error"
+        })?.map(|hash| format!("{:?}", hash));
+        let indexed = forks.hash(fork).await.map_err(unexpected)?;
+        if indexed.is_some() && indexed == canonical {
+            break;
+        }
+        fork -= 1;
+    }
+
+    let stale = forks.truncate(fork).await.map_err(unexpected)?;
+    if !stale.is_empty() {
+        llog::warn!("chain reorganization at #{}, roll back {} blocks", fork, stale.len());
+        sink.remove(&stale).await.map_err(unexpected)?;
+    }
+    Ok(fork)
+}
+
+/// block numbers not processed yet, at most `PULL_BATCH_SIZE` per pull
+fn decode_range(checkpoint: Option<u64>, head: u64) -> Option<RangeInclusive<u64>> {
+    let start = checkpoint.map_or(0, |number| number + 1);
+    if start > head {
+        return None;
+    }
+    Some(start..=head.min(start + PULL_BATCH_SIZE - 1))
+}
+
+/// first `blocks` blocks of range, `None` if `blocks` is 0
+fn limit_range(range: RangeInclusive<u64>, blocks: u64) -> Option<RangeInclusive<u64>> {
+    if blocks == 0 {
+        return None;
+    }
+    Some(*range.start()..=(*range.end()).min(range.start() + blocks - 1))
+}
+
+/// decode_block tasks to dispatch for `chunks` queued block chunks
+pub(crate) fn decode_tasks(chunks: usize) -> usize {
+    (chunks + CHUNKS_PER_TASK - 1) / CHUNKS_PER_TASK
+}
+
+/// split range into `(from, to)` chunks of `size` blocks, a `size` of 0 counts as 1
+pub(crate) fn chunks(range: &RangeInclusive<u64>, size: u64) -> Vec<(u64, u64)> {
+    let size = size.max(1);
+    let end = *range.end();
+    range.clone()
+        .step_by(size as usize)
+        .map(|from| (from, end.min(from + size - 1)))
+        .collect()
+}
+
+async fn block_decoder(settings: &Settings, state: &AppState<'_>) -> TaskResult<BlockDecoder> {
+    let mut builder = runtime::register_type_sizes(ClientBuilder::<Runtime>::new())
+        .set_url(env::var("CHAIN_RPC_URL").unwrap_or_else(|_| settings.chain.rpc_url.clone()));
+    if settings.chain.decoder == DecoderMode::Dynamic {
+        // types come from runtime metadata, the compile-time runtime only provides hash and header
+        builder = builder.skip_type_sizes_check();
+    }
+    let client = builder.build().await.with_unexpected_err(|| {
+        "Chain node server error"
+    })?;
+    Ok(BlockDecoder::new(client, &settings.chain, state.redis_client.clone()))
+}
+
+pub(crate) async fn dispatcher() -> TaskResult<Arc<Celery<RedisBroker>>> {
+    celery::app!(
+        broker = RedisBroker { env::var("REDIS_ADDR").unwrap_or_else(|_| "redis://127.0.0.1:6379/".into())},
+        tasks = [
+            decode_block,
+        ],
+        task_routes = [
+            "*" => QUEUE_NAME,
+        ],
+    ).await.with_unexpected_err(|| {
+        "celery broker error"
+    })
+}
+
+/// processing list owner, one per worker process
+fn worker_id() -> String {
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("{}:{}", host, std::process::id())
+}
+
+fn load_settings() -> TaskResult<Settings> {
+    let config_file = env::current_dir().with_unexpected_err(|| {
+        "get current dir error"
+    })?.join(CONFIG_FILE);
+    Settings::build(config_file).with_unexpected_err(|| {
+        "load explorer settings error"
+    })
+}
+
+fn unexpected(e: Error) -> TaskError {
+    TaskError::UnexpectedError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_range_without_checkpoint_starts_at_genesis() {
+        assert_eq!(decode_range(None, 10), Some(0..=10));
+    }
+
+    #[test]
+    fn decode_range_is_empty_at_head() {
+        assert_eq!(decode_range(Some(10), 10), None);
+        assert_eq!(decode_range(Some(11), 10), None);
+    }
+
+    #[test]
+    fn decode_range_is_capped_by_pull_batch_size() {
+        assert_eq!(decode_range(Some(9), 1_000_000), Some(10..=9 + PULL_BATCH_SIZE));
+        assert_eq!(decode_range(None, 1_000_000), Some(0..=PULL_BATCH_SIZE - 1));
+    }
+
+    #[test]
+    fn limit_range_keeps_the_start() {
+        assert_eq!(limit_range(10..=100, 0), None);
+        assert_eq!(limit_range(10..=100, 5), Some(10..=14));
+        assert_eq!(limit_range(10..=100, 1000), Some(10..=100));
+    }
+
+    #[test]
+    fn chunks_cover_range() {
+        assert_eq!(chunks(&(0..=9), 5), vec![(0, 4), (5, 9)]);
+        assert_eq!(chunks(&(3..=3), 10), vec![(3, 3)]);
+    }
+
+    #[test]
+    fn zero_chunk_size_does_not_panic() {
+        assert_eq!(chunks(&(0..=2), 0), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn decode_tasks_cover_chunks() {
+        assert_eq!(decode_tasks(0), 0);
+        assert_eq!(decode_tasks(1), 1);
+        assert_eq!(decode_tasks(CHUNKS_PER_TASK), 1);
+        assert_eq!(decode_tasks(CHUNKS_PER_TASK + 1), 2);
+    }
+
+    #[test]
+    fn last_chunk_is_shorter() {
+        assert_eq!(chunks(&(10..=22), 5), vec![(10, 14), (15, 19), (20, 22)]);
+    }
+}